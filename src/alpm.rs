@@ -1,25 +1,154 @@
 use super::error::{AlpmError, alpm_init_error, alpm_install_error, alpm_remove_error};
 use alpm::Alpm;
 use alpm::SigLevel;
-use std::process::Command;
+use crate::exec::{CommandRunner, Invocation, SystemRunner};
+use crate::srcinfo::strip_constraint;
+use crate::progress;
 use std::path::Path;
 use colored::Colorize;
-use std::sync::Arc;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use log::{info, error, debug};
+use serde::Serialize;
 
-pub struct AlpmWrapper {
-    alpm: Arc<Alpm>,
+/// Metadata of a package from a sync database
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepoPackage {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub arch: Option<String>,
+    pub licenses: Vec<String>,
+    pub depends: Vec<String>,
+    pub packager: Option<String>,
+    pub build_date: i64,
+    pub installed_size: i64,
 }
 
-impl AlpmWrapper {
+impl RepoPackage {
+    pub fn new(name: &str, version: &str) -> Self {
+        RepoPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn from_alpm(pkg: &alpm::Pkg) -> Self {
+        RepoPackage {
+            name: pkg.name().to_string(),
+            version: pkg.version().to_string(),
+            description: pkg.desc().map(str::to_string),
+            url: pkg.url().map(str::to_string),
+            arch: pkg.arch().map(str::to_string),
+            licenses: pkg.licenses().iter().map(str::to_string).collect(),
+            depends: pkg.depends().iter().map(|dep| dep.to_string()).collect(),
+            packager: pkg.packager().map(str::to_string),
+            build_date: pkg.build_date(),
+            installed_size: pkg.isize(),
+        }
+    }
+}
+
+/// Extra pacman behaviour for `remove_package`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemoveOptions {
+    /// Also remove packages that depend on the targets (`-c`)
+    pub cascade: bool,
+    /// Don't keep `.pacsave` backups of modified config files (`-n`)
+    pub nosave: bool,
+}
+
+impl RemoveOptions {
+    /// The combined pacman flag, such as `-Rscn`
+    pub fn pacman_flag(self) -> String {
+        let mut flag = String::from("-Rs");
+        if self.cascade {
+            flag.push('c');
+        }
+        if self.nosave {
+            flag.push('n');
+        }
+        flag
+    }
+}
+
+/// How pacman is run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacmanOptions {
+    /// Command that runs pacman as root, such as `sudo` or `doas`. Empty runs
+    /// pacman directly.
+    pub escalation: String,
+    /// Answer pacman's prompts with their defaults
+    pub noconfirm: bool,
+}
+
+impl Default for PacmanOptions {
+    fn default() -> Self {
+        PacmanOptions { escalation: "sudo".to_string(), noconfirm: false }
+    }
+}
+
+impl PacmanOptions {
+    /// `<escalation> pacman [--noconfirm]`, ready for the operation's arguments
+    pub fn invocation(&self) -> Invocation {
+        let mut escalation = self.escalation.split_whitespace();
+        let invocation = match escalation.next() {
+            Some(program) => Invocation::new(program).args(escalation).arg("pacman"),
+            None => Invocation::new("pacman"),
+        };
+        if self.noconfirm {
+            invocation.arg("--noconfirm")
+        } else {
+            invocation
+        }
+    }
+}
+
+/// The package database queries lilac makes, kept behind a trait so commands can
+/// run against `FakeDb` without libalpm or /var/lib/pacman
+pub trait PackageDb {
+    fn is_package_installed(&self, package_name: &str) -> Result<bool, AlpmError>;
+
+    /// Version of a package in the local database, if it is installed
+    fn installed_version(&self, package_name: &str) -> Result<Option<String>, AlpmError>;
+
+    /// Searches every sync database for packages whose name or description matches
+    /// all terms. Results are paired with the name of their repo.
+    fn search_repos(&self, terms: &[String]) -> Result<Vec<(String, RepoPackage)>, AlpmError>;
+
+    /// Looks a package up in the sync databases, returning the first repo that has it
+    fn repo_package(&self, package_name: &str) -> Result<Option<(String, RepoPackage)>, AlpmError>;
+
+    /// Name and dependencies of the installed package satisfying a dependency, which
+    /// may be a provider with another name
+    fn installed_satisfier(&self, dep: &str) -> Option<(String, Vec<String>)>;
+
+    /// The sync database package satisfying a dependency, paired with its repo
+    fn repo_satisfier(&self, dep: &str) -> Option<(String, RepoPackage)>;
+
+    /// Whether an installed package was installed explicitly rather than as a dependency
+    fn is_explicitly_installed(&self, package_name: &str) -> Result<bool, AlpmError>;
+
+    /// Installed packages that depend on an installed package
+    fn required_by(&self, package_name: &str) -> Result<Vec<String>, AlpmError>;
+
+    fn is_package_available(&self, package_name: &str) -> Result<bool, AlpmError>;
+}
+
+/// The host's package database, read through libalpm
+pub struct AlpmDb {
+    alpm: Alpm,
+}
+
+impl AlpmDb {
     pub fn new() -> Result<Self, AlpmError> {
         let alpm = Alpm::new("/", "/var/lib/pacman")
             .map_err(|e| alpm_init_error(format!("Failed to initialize ALPM: {}", e)))?;
-        let wrapper = AlpmWrapper { alpm: Arc::new(alpm) };
-        wrapper.load_syncdbs_from_pacman_conf()?;
-        Ok(wrapper)
+        let db = AlpmDb { alpm };
+        db.load_syncdbs_from_pacman_conf()?;
+        Ok(db)
     }
 
     /// Loads enabled syncdbs from /etc/pacman.conf and registers them with ALPM
@@ -44,9 +173,11 @@ impl AlpmWrapper {
         }
         Ok(())
     }
+}
 
+impl PackageDb for AlpmDb {
     // Checks if a package is installed
-    pub fn is_package_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+    fn is_package_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
         self.alpm.localdb().pkg(package_name)
             .map(|_| true)
             .or_else(|e| match e {
@@ -55,35 +186,80 @@ impl AlpmWrapper {
             })
     }
 
-    pub fn install_package(&self, package_path: &Path) -> Result<(), AlpmError> {
-        println!(
-            "{} {} {} {}",
-            "Installing:".bold(),
-            package_path.file_name().unwrap().to_str().unwrap().bright_green(),
-            "from:".bold(),
-            package_path.parent().unwrap().display().to_string().bright_cyan()
-        );
+    /// Version of a package in the local database, if it is installed
+    fn installed_version(&self, package_name: &str) -> Result<Option<String>, AlpmError> {
+        match self.alpm.localdb().pkg(package_name) {
+            Ok(pkg) => Ok(Some(pkg.version().to_string())),
+            Err(alpm::Error::PkgNotFound) => Ok(None),
+            Err(e) => Err(AlpmError::DatabaseError(format!("Database query failed: {}", e))),
+        }
+    }
 
-        let status = Command::new("sudo")
-            .arg("pacman")
-            .arg("-U")
-            .arg(package_path)
-            .status()
-            .map_err(|e| alpm_install_error(format!("Failed to execute pacman: {}", e)))?;
+    /// Searches every registered sync database for packages whose name or description
+    /// matches all terms. Results are paired with the name of their repo.
+    fn search_repos(&self, terms: &[String]) -> Result<Vec<(String, RepoPackage)>, AlpmError> {
+        let mut results = Vec::new();
+        for db in self.alpm.syncdbs() {
+            let found = db.search(terms.iter().map(String::as_str))
+                .map_err(|e| AlpmError::DatabaseError(format!(
+                    "Search failed in repo '{}': {}", db.name(), e
+                )))?;
+            for pkg in found.iter() {
+                results.push((db.name().to_string(), RepoPackage::from_alpm(pkg)));
+            }
+        }
+        Ok(results)
+    }
 
-        if !status.success() {
-            Err(alpm_install_error(format!(
-                "pacman failed with exit code: {}",
-                status
-            )))
-        } else {
-            println!("\n{}", "✓ Successfully installed!\n".green().bold());
-            Ok(())
+    /// Looks a package up in the sync databases, returning the first repo that has it
+    fn repo_package(&self, package_name: &str) -> Result<Option<(String, RepoPackage)>, AlpmError> {
+        for db in self.alpm.syncdbs() {
+            match db.pkg(package_name) {
+                Ok(pkg) => return Ok(Some((db.name().to_string(), RepoPackage::from_alpm(pkg)))),
+                Err(alpm::Error::PkgNotFound) => continue,
+                Err(e) => return Err(AlpmError::DatabaseError(format!(
+                    "Database query failed in repo '{}': {}", db.name(), e
+                ))),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Name and dependencies of the installed package satisfying a dependency, which
+    /// may be a provider with another name
+    fn installed_satisfier(&self, dep: &str) -> Option<(String, Vec<String>)> {
+        self.alpm.localdb().pkgs().find_satisfier(dep)
+            .map(|pkg| (pkg.name().to_string(), pkg.depends().iter().map(|d| d.to_string()).collect()))
+    }
+
+    /// The sync database package satisfying a dependency, paired with its repo
+    fn repo_satisfier(&self, dep: &str) -> Option<(String, RepoPackage)> {
+        self.alpm.syncdbs().find_satisfier(dep).map(|pkg| (
+            pkg.db().map(|db| db.name().to_string()).unwrap_or_default(),
+            RepoPackage::from_alpm(pkg),
+        ))
+    }
+
+    /// Whether an installed package was installed explicitly rather than as a dependency
+    fn is_explicitly_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+        match self.alpm.localdb().pkg(package_name) {
+            Ok(pkg) => Ok(pkg.reason() == alpm::PackageReason::Explicit),
+            Err(alpm::Error::PkgNotFound) => Err(AlpmError::NotFound(package_name.to_string())),
+            Err(e) => Err(AlpmError::DatabaseError(format!("Database query failed: {}", e))),
+        }
+    }
+
+    /// Installed packages that depend on an installed package
+    fn required_by(&self, package_name: &str) -> Result<Vec<String>, AlpmError> {
+        match self.alpm.localdb().pkg(package_name) {
+            Ok(pkg) => Ok(pkg.required_by().iter().map(|name| name.to_string()).collect()),
+            Err(alpm::Error::PkgNotFound) => Err(AlpmError::NotFound(package_name.to_string())),
+            Err(e) => Err(AlpmError::DatabaseError(format!("Database query failed: {}", e))),
         }
     }
 
     // Checks if a package is available in the official repositories.
-    pub fn is_package_available(&self, package_name: &str) -> Result<bool, AlpmError> {
+    fn is_package_available(&self, package_name: &str) -> Result<bool, AlpmError> {
         let mut found = false;
         for db in self.alpm.syncdbs() {
             let db_name = db.name();
@@ -117,56 +293,324 @@ impl AlpmWrapper {
         }
         Ok(found)
     }
+}
+
+/// An installed package of a `FakeDb`
+#[derive(Debug, Clone)]
+struct FakeInstalled {
+    package: RepoPackage,
+    explicit: bool,
+}
+
+/// In-memory package database for tests. Dependencies are satisfied by name only.
+#[derive(Debug, Clone, Default)]
+pub struct FakeDb {
+    installed: Vec<FakeInstalled>,
+    repos: Vec<(String, RepoPackage)>,
+}
+
+impl FakeDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_installed(mut self, name: &str, version: &str, depends: &[&str], explicit: bool) -> Self {
+        let mut package = RepoPackage::new(name, version);
+        package.depends = depends.iter().map(|dep| dep.to_string()).collect();
+        self.installed.push(FakeInstalled { package, explicit });
+        self
+    }
+
+    pub fn with_repo_package(mut self, repo: &str, package: RepoPackage) -> Self {
+        self.repos.push((repo.to_string(), package));
+        self
+    }
+
+    fn installed(&self, package_name: &str) -> Option<&FakeInstalled> {
+        self.installed.iter().find(|installed| installed.package.name == package_name)
+    }
+}
+
+impl PackageDb for FakeDb {
+    fn is_package_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+        Ok(self.installed(package_name).is_some())
+    }
+
+    fn installed_version(&self, package_name: &str) -> Result<Option<String>, AlpmError> {
+        Ok(self.installed(package_name).map(|installed| installed.package.version.clone()))
+    }
+
+    fn search_repos(&self, terms: &[String]) -> Result<Vec<(String, RepoPackage)>, AlpmError> {
+        Ok(self.repos.iter()
+            .filter(|(_, package)| terms.iter().all(|term| {
+                let term = term.to_lowercase();
+                package.name.to_lowercase().contains(&term)
+                    || package.description.as_deref().is_some_and(|desc| desc.to_lowercase().contains(&term))
+            }))
+            .cloned()
+            .collect())
+    }
+
+    fn repo_package(&self, package_name: &str) -> Result<Option<(String, RepoPackage)>, AlpmError> {
+        Ok(self.repos.iter().find(|(_, package)| package.name == package_name).cloned())
+    }
+
+    fn installed_satisfier(&self, dep: &str) -> Option<(String, Vec<String>)> {
+        self.installed(&strip_constraint(dep))
+            .map(|installed| (installed.package.name.clone(), installed.package.depends.clone()))
+    }
+
+    fn repo_satisfier(&self, dep: &str) -> Option<(String, RepoPackage)> {
+        self.repo_package(&strip_constraint(dep)).ok().flatten()
+    }
+
+    fn is_explicitly_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+        self.installed(package_name)
+            .map(|installed| installed.explicit)
+            .ok_or_else(|| AlpmError::NotFound(package_name.to_string()))
+    }
+
+    fn required_by(&self, package_name: &str) -> Result<Vec<String>, AlpmError> {
+        if self.installed(package_name).is_none() {
+            return Err(AlpmError::NotFound(package_name.to_string()));
+        }
+        Ok(self.installed.iter()
+            .filter(|installed| installed.package.depends.iter().any(|dep| strip_constraint(dep) == package_name))
+            .map(|installed| installed.package.name.clone())
+            .collect())
+    }
+
+    fn is_package_available(&self, package_name: &str) -> Result<bool, AlpmError> {
+        Ok(self.repos.iter().any(|(_, package)| package.name == package_name))
+    }
+}
+
+pub struct AlpmWrapper {
+    db: Box<dyn PackageDb>,
+    runner: Box<dyn CommandRunner>,
+    pacman_options: PacmanOptions,
+}
+
+impl AlpmWrapper {
+    pub fn new() -> Result<Self, AlpmError> {
+        Ok(Self::with_db(AlpmDb::new()?))
+    }
+
+    /// Answers package queries from `db` instead of the host's database
+    pub fn with_db(db: impl PackageDb + 'static) -> Self {
+        AlpmWrapper {
+            db: Box::new(db),
+            runner: Box::new(SystemRunner::default()),
+            pacman_options: PacmanOptions::default(),
+        }
+    }
+
+    /// Runs pacman, and the builder's makepkg calls, through `runner` instead of
+    /// the host
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    pub fn with_pacman_options(mut self, options: PacmanOptions) -> Self {
+        self.pacman_options = options;
+        self
+    }
+
+    pub fn pacman_options(&self) -> &PacmanOptions {
+        &self.pacman_options
+    }
+
+    /// The runner every pacman call goes through, shared with the builder
+    pub fn runner(&self) -> &dyn CommandRunner {
+        self.runner.as_ref()
+    }
+
+    pub fn is_package_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+        self.db.is_package_installed(package_name)
+    }
+
+    pub fn installed_version(&self, package_name: &str) -> Result<Option<String>, AlpmError> {
+        self.db.installed_version(package_name)
+    }
+
+    pub fn search_repos(&self, terms: &[String]) -> Result<Vec<(String, RepoPackage)>, AlpmError> {
+        self.db.search_repos(terms)
+    }
+
+    pub fn repo_package(&self, package_name: &str) -> Result<Option<(String, RepoPackage)>, AlpmError> {
+        self.db.repo_package(package_name)
+    }
+
+    pub fn installed_satisfier(&self, dep: &str) -> Option<(String, Vec<String>)> {
+        self.db.installed_satisfier(dep)
+    }
+
+    pub fn repo_satisfier(&self, dep: &str) -> Option<(String, RepoPackage)> {
+        self.db.repo_satisfier(dep)
+    }
+
+    pub fn is_explicitly_installed(&self, package_name: &str) -> Result<bool, AlpmError> {
+        self.db.is_explicitly_installed(package_name)
+    }
+
+    pub fn required_by(&self, package_name: &str) -> Result<Vec<String>, AlpmError> {
+        self.db.required_by(package_name)
+    }
+
+    pub fn is_package_available(&self, package_name: &str) -> Result<bool, AlpmError> {
+        self.db.is_package_available(package_name)
+    }
+
+    /// The pacman calls made through this wrapper's runner
+    pub fn pacman(&self) -> Pacman<'_> {
+        Pacman::new(self.runner.as_ref()).with_options(self.pacman_options.clone())
+    }
+
+    pub fn install_package(&self, package_path: &Path) -> Result<(), AlpmError> {
+        self.pacman().install_package(package_path)
+    }
+
+    pub fn remove_package(&self, package_names: &[String], options: RemoveOptions) -> Result<(), AlpmError> {
+        self.pacman().remove_package(package_names, options)
+    }
+
+    pub fn force_remove_package(&self, package_name: &str) -> Result<(), AlpmError> {
+        self.pacman().force_remove_package(package_name)
+    }
+
+    pub fn install_repo_packages(&self, package_names: &[String]) -> Result<(), AlpmError> {
+        self.pacman().install_repo_packages(package_names)
+    }
+
+    pub fn install_packages(&self, package_paths: &[std::path::PathBuf]) -> Result<(), AlpmError> {
+        self.pacman().install_packages(package_paths)
+    }
+}
+
+/// The pacman invocations lilac makes, kept apart from the ALPM handle so they
+/// can be exercised with any `CommandRunner`
+pub struct Pacman<'a> {
+    runner: &'a dyn CommandRunner,
+    options: PacmanOptions,
+}
+
+impl<'a> Pacman<'a> {
+    pub fn new(runner: &'a dyn CommandRunner) -> Self {
+        Pacman { runner, options: PacmanOptions::default() }
+    }
+
+    pub fn with_options(mut self, options: PacmanOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn install_package(&self, package_path: &Path) -> Result<(), AlpmError> {
+        progress!(
+            "{} {} {} {}",
+            "Installing:".bold(),
+            package_path.file_name().unwrap().to_str().unwrap().bright_green(),
+            "from:".bold(),
+            package_path.parent().unwrap().display().to_string().bright_cyan()
+        );
+
+        let status = self.runner.run(&self.options.invocation()
+            .arg("-U")
+            .arg(package_path))
+            .map_err(|e| alpm_install_error(format!("Failed to execute pacman: {}", e)))?;
+
+        if !status.success {
+            Err(alpm_install_error(format!(
+                "pacman failed with exit code: {}",
+                status
+            )))
+        } else {
+            if !self.runner.is_dry_run() {
+                progress!("\n{}", "✓ Successfully installed!\n".green().bold());
+            }
+            Ok(())
+        }
+    }
 
     // Removes a package from the system recursively, removing dependencies no longer needed
-    pub fn remove_package(&self, package_names: &[String]) -> Result<(), AlpmError> {
-        println!(
+    pub fn remove_package(&self, package_names: &[String], options: RemoveOptions) -> Result<(), AlpmError> {
+        progress!(
             "{} {:?} {}",
             "Removing:".bold(),
             package_names,
             "from the system".bold()
         );
 
-        let status = Command::new("sudo")
-            .arg("pacman")
-            .arg("-Rs")
-            .args(package_names)
-            .status()
+        let flag = options.pacman_flag();
+        let status = self.runner.run(&self.options.invocation()
+            .arg(&flag)
+            .args(package_names))
             .map_err(|e| alpm_remove_error(format!("Failed to execute pacman for removal: {}", e)))?;
 
-        if !status.success() {
+        if !status.success {
             Err(alpm_remove_error(format!(
-                "pacman -Rs failed with exit code: {}",
-                status
+                "pacman {} failed with exit code: {}",
+                flag, status
             )))
         } else {
-            println!("\n{}\n", "✓ Successfully removed!".green().bold());
+            if !self.runner.is_dry_run() {
+                progress!("\n{}\n", "✓ Successfully removed!".green().bold());
+            }
             Ok(())
         }
     }
 
     pub fn force_remove_package(&self, package_name: &str) -> Result<(), AlpmError> {
-        println!(
+        progress!(
             "{} {} {}",
             "Forcibly removing:".bold(),
             package_name.bright_green(),
             "from the system (bypassing dependency checks)".bold()
         );
 
-        let status = Command::new("sudo")
-            .arg("pacman")
+        let status = self.runner.run(&self.options.invocation()
             .arg("-R")
-            .arg(package_name)
-            .status()
+            .arg(package_name))
             .map_err(|e| alpm_remove_error(format!("Failed to execute pacman for forced removal: {}", e)))?;
 
-        if !status.success() {
+        if !status.success {
             Err(alpm_remove_error(format!(
                 "pacman -R failed with exit code: {}",
                 status
             )))
         } else {
-            println!("\n{}", "✓ Successfully force removed!".green().bold());
+            if !self.runner.is_dry_run() {
+                progress!("\n{}", "✓ Successfully force removed!".green().bold());
+            }
+            Ok(())
+        }
+    }
+
+    /// Installs packages from the sync databases with `pacman -S --needed`
+    pub fn install_repo_packages(&self, package_names: &[String]) -> Result<(), AlpmError> {
+        if package_names.is_empty() {
+            return Ok(());
+        }
+        progress!(
+            "{} {:?} {}",
+            "Installing:".bold(),
+            package_names,
+            "from the repositories".bold()
+        );
+        let status = self.runner.run(&self.options.invocation()
+            .arg("-S")
+            .arg("--needed")
+            .args(package_names))
+            .map_err(|e| alpm_install_error(format!("Failed to execute pacman: {}", e)))?;
+        if !status.success {
+            Err(alpm_install_error(format!(
+                "pacman -S failed with exit code: {}",
+                status
+            )))
+        } else {
+            if !self.runner.is_dry_run() {
+                progress!("\n{}\n", "✓ Successfully installed all packages!".green().bold());
+            }
             Ok(())
         }
     }
@@ -175,25 +619,25 @@ impl AlpmWrapper {
         if package_paths.is_empty() {
             return Ok(());
         }
-        println!(
+        progress!(
             "{} {:?} {}",
             "Installing:".bold(),
             package_paths.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect::<Vec<_>>(),
             "from cache/built packages".bold()
         );
-        let status = std::process::Command::new("sudo")
-            .arg("pacman")
+        let status = self.runner.run(&self.options.invocation()
             .arg("-U")
-            .args(package_paths)
-            .status()
+            .args(package_paths))
             .map_err(|e| alpm_install_error(format!("Failed to execute pacman: {}", e)))?;
-        if !status.success() {
+        if !status.success {
             Err(alpm_install_error(format!(
                 "pacman -U failed with exit code: {}",
                 status
             )))
         } else {
-            println!("\n{}\n", "✓ Successfully installed all packages!".green().bold());
+            if !self.runner.is_dry_run() {
+                progress!("\n{}\n", "✓ Successfully installed all packages!".green().bold());
+            }
             Ok(())
         }
     }
@@ -1,86 +1,392 @@
-use crate::error::{AurError, aur_request_failed, aur_parse_error, aur_api_error};
-use reqwest::Client;
-use serde::Deserialize;
+use crate::error::{AurError, BuildError, aur_request_failed, aur_parse_error, aur_api_error, aur_rpc_error};
+use crate::http_cache::{CachedResponse, ResponseCache};
+use crate::network::NetworkSettings;
+use config::ConfigError;
+use crate::sources::PackageSource;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
+use clap::ValueEnum;
 use serde_json;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AurPackage {
-    #[serde(rename = "Name")]
+    #[serde(rename(deserialize = "Name"))]
     pub name: String,
-    #[serde(rename = "Version")]
+    #[serde(rename(deserialize = "PackageBase"), default)]
+    pub package_base: Option<String>,
+    #[serde(rename(deserialize = "Version"))]
     pub version: String,
-    #[serde(rename = "Description")]
+    #[serde(rename(deserialize = "Description"))]
     pub description: Option<String>,
-    #[serde(rename = "URL")]
+    #[serde(rename(deserialize = "URL"))]
     pub url: Option<String>,
-    #[serde(rename = "Maintainer")]
+    #[serde(rename(deserialize = "Maintainer"))]
     pub maintainer: Option<String>,
-    #[serde(rename = "NumVotes")]
+    #[serde(rename(deserialize = "NumVotes"))]
     pub num_votes: u32,
-    #[serde(rename = "Popularity")]
+    #[serde(rename(deserialize = "Popularity"))]
     pub popularity: f32,
-    #[serde(rename = "FirstSubmitted")]
+    #[serde(rename(deserialize = "OutOfDate"), default)]
+    pub out_of_date: Option<u64>,
+    #[serde(rename(deserialize = "FirstSubmitted"))]
     pub first_submitted: u64,
-    #[serde(rename = "LastModified")]
+    #[serde(rename(deserialize = "LastModified"))]
     pub last_modified: u64,
 }
 
+/// Field the AUR RPC matches a search term against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SearchField {
+    #[default]
+    Name,
+    NameDesc,
+    Maintainer,
+    Depends,
+    Makedepends,
+    Optdepends,
+    Checkdepends,
+    Provides,
+    Keywords,
+    Submitter,
+    Comaintainers,
+}
+
+impl SearchField {
+    /// Value of the RPC `by` parameter
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SearchField::Name => "name",
+            SearchField::NameDesc => "name-desc",
+            SearchField::Maintainer => "maintainer",
+            SearchField::Depends => "depends",
+            SearchField::Makedepends => "makedepends",
+            SearchField::Optdepends => "optdepends",
+            SearchField::Checkdepends => "checkdepends",
+            SearchField::Provides => "provides",
+            SearchField::Keywords => "keywords",
+            SearchField::Submitter => "submitter",
+            SearchField::Comaintainers => "comaintainers",
+        }
+    }
+}
+
+/// Daily dump of every AUR package's metadata, served next to the RPC
+pub const META_ARCHIVE_NAME: &str = "packages-meta-ext-v1.json.gz";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AurResponse {
+    pub(crate) results: Vec<AurPackage>,
+}
+
+/// Body of a failed RPC call, such as `{"type": "error", "error": "Too many package results."}`
 #[derive(Debug, Deserialize)]
-struct AurResponse {
-    results: Vec<AurPackage>,
+struct RpcErrorBody {
+    #[serde(rename = "type")]
+    kind: String,
+    error: String,
+}
+
+/// Percent-encodes a query value, so terms and names with `+`, `&` or spaces
+/// reach the RPC intact
+fn encode_query(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn rpc_error(body: &str) -> Option<AurError> {
+    serde_json::from_str::<RpcErrorBody>(body).ok()
+        .filter(|body| body.kind == "error")
+        .map(|body| aur_rpc_error(body.error))
+}
+
+/// Statuses worth another attempt
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Wait asked for by a `Retry-After` header, in seconds or as an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// How failed requests are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every retry after it
+    pub base_delay: Duration,
+    /// Longest wait between attempts. A 429 asking for longer fails right away.
+    pub max_delay: Duration,
 }
 
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the given retry, counting from 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(retry)).min(self.max_delay)
+    }
+}
+
+/// Settings of the HTTP client talking to the AUR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    pub timeout: Duration,
+    pub network: NetworkSettings,
+    pub retry: RetryPolicy,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            timeout: Duration::from_secs(10),
+            network: NetworkSettings::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+
 pub struct AurClient {
     base_url: String,
     client: Client,
+    retry: RetryPolicy,
+    cache: ResponseCache,
 }
 
 impl AurClient {
     pub fn new(base_url: String) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(10))
+        Self::with_options(base_url, ClientOptions::default()).expect("Failed to create HTTP client")
+    }
+
+    /// Fails when the proxy or a CA bundle in the network settings is unusable
+    pub fn with_options(base_url: String, options: ClientOptions) -> Result<Self, ConfigError> {
+        let client = options.network.apply(Client::builder().timeout(options.timeout))?
             .build()
-            .expect("Failed to create HTTP client");
-            
-        AurClient { base_url, client }
+            .map_err(|e| ConfigError::Message(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(AurClient { base_url, client, retry: options.retry, cache: ResponseCache::in_memory() })
     }
 
-    pub async fn search_packages(&self, query: &str) -> Result<Vec<AurPackage>, AurError> {
-        let url = format!("{}/rpc/?v=5&type=search&by=name&arg={}", self.base_url, query);
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = cache;
+        self
+    }
 
-        let response = self.client.get(&url)
-            .send()
-            .await
-            .map_err(|e| aur_request_failed(e.to_string()))?;
+    /// Sends a request, retrying transport errors and transient statuses with
+    /// exponential backoff. A 429 waits as long as its `Retry-After` asks.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, AurError> {
+        let mut retry = 0;
+        loop {
+            let attempt = request.try_clone().expect("Bodyless requests can be cloned");
+            let delay = match attempt.send().await {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = retry_after(&response).unwrap_or_else(|| self.retry.delay(retry));
+                    if retry >= self.retry.max_retries || delay > self.retry.max_delay {
+                        return Err(AurError::RateLimited(format!("retry after {}s", delay.as_secs())));
+                    }
+                    delay
+                }
+                Ok(response) if is_transient(response.status()) && retry < self.retry.max_retries => {
+                    debug!("{} answered {}", response.url(), response.status());
+                    self.retry.delay(retry)
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if (e.is_timeout() || e.is_connect() || e.is_request()) && retry < self.retry.max_retries => {
+                    debug!("Request failed: {}", e);
+                    self.retry.delay(retry)
+                }
+                Err(e) => return Err(aur_request_failed(format!("Request failed: {}", e))),
+            };
 
-        if !response.status().is_success() {
-            return Err(aur_api_error(format!("Status: {}", response.status())));
+            retry += 1;
+            info!("Retrying AUR request in {:?} ({}/{})", delay, retry, self.retry.max_retries);
+            tokio::time::sleep(delay).await;
         }
+    }
 
-        let raw_response = response.text().await.map_err(|e| aur_parse_error(e.to_string()))?;
-        serde_json::from_str::<AurResponse>(&raw_response)
-            .map(|r| r.results)
-            .map_err(|e| aur_parse_error(e.to_string()))
+    /// GETs a URL through the response cache. Stale entries are revalidated with
+    /// the validators the server sent, and a 404 becomes `AurError::NotFound`.
+    async fn get_text(&self, url: &str) -> Result<String, AurError> {
+        if let Some(body) = self.cache.memoized(url) {
+            return Ok(body);
+        }
+        let stored = self.cache.load(url);
+        if let Some(entry) = &stored
+            && entry.is_fresh(self.cache.ttl())
+        {
+            debug!("Using cached response for {}", url);
+            self.cache.memoize(url, &entry.body);
+            return Ok(entry.body.clone());
+        }
+
+        let mut request = self.client.get(url);
+        if let Some(entry) = &stored {
+            if let Some(etag) = &entry.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = self.send(request).await?;
+
+        let status = response.status();
+        let entry = match stored {
+            Some(entry) if status == StatusCode::NOT_MODIFIED => {
+                debug!("Cached response for {} is still valid", url);
+                entry.revalidated()
+            }
+            _ if status == StatusCode::NOT_FOUND => return Err(AurError::NotFound(url.to_string())),
+            _ if !status.is_success() => {
+                let body = response.text().await.unwrap_or_default();
+                return Err(rpc_error(&body).unwrap_or_else(|| aur_api_error(format!("Status: {}", status))));
+            }
+            _ => {
+                let header = |name: HeaderName| response.headers().get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+                let body = response.text().await.map_err(|e| aur_parse_error(e.to_string()))?;
+                // The RPC reports some errors with a 200
+                if let Some(e) = rpc_error(&body) {
+                    return Err(e);
+                }
+                CachedResponse::new(url, etag, last_modified, body)
+            }
+        };
+
+        self.cache.store(&entry);
+        self.cache.memoize(url, &entry.body);
+        Ok(entry.body)
     }
 
-    pub async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError> {
-        let url = format!("{}/rpc/?v=5&type=info&arg={}", self.base_url, package_name);
+    /// Downloads the metadata archive to `dest`. The archive is large, so it gets a
+    /// longer timeout than RPC requests.
+    pub async fn download_meta_archive(&self, dest: &Path) -> Result<u64, AurError> {
+        let url = format!("{}/{}", self.base_url, META_ARCHIVE_NAME);
 
-        let response = self.client.get(&url)
-            .send()
-            .await
-            .map_err(|e| aur_request_failed(format!("Request failed: {}", e)))?;
+        let response = self.send(self.client.get(&url).timeout(Duration::from_secs(300))).await?;
 
         if !response.status().is_success() {
             return Err(aur_api_error(format!("Status: {}", response.status())));
         }
 
-        let mut aur_response: AurResponse = response.json()
+        let bytes = response.bytes()
             .await
+            .map_err(|e| aur_request_failed(format!("Download failed: {}", e)))?;
+        fs::write(dest, &bytes)
+            .map_err(|e| aur_request_failed(format!("Failed to write {:?}: {}", dest, e)))?;
+
+        Ok(bytes.len() as u64)
+    }
+}
+
+/// Everything lilac needs from the AUR. `AurClient` talks to the real RPC, while
+/// `FixtureAur` and `MockAur` serve packages from disk or memory.
+#[async_trait]
+pub trait AurBackend: Send + Sync {
+    async fn search_packages(&self, query: &str) -> Result<Vec<AurPackage>, AurError> {
+        self.search_packages_by(query, SearchField::Name).await
+    }
+
+    async fn search_packages_by(&self, query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError>;
+
+    async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError>;
+
+    /// Info for several packages at once. Packages that don't exist are simply
+    /// absent from the result.
+    async fn get_packages_info(&self, package_names: &[String]) -> Result<Vec<AurPackage>, AurError>;
+
+    /// One file of a pkgbase's git repo
+    async fn get_repo_file(&self, pkgbase: &str, file: &str) -> Result<String, AurError>;
+
+    /// Clones the git repo of a pkgbase into `dest`. Returns false when the backend
+    /// doesn't have it.
+    fn clone_repo(&self, pkgbase: &str, dest: &Path) -> Result<bool, BuildError>;
+}
+
+#[async_trait]
+impl AurBackend for AurClient {
+    async fn search_packages_by(&self, query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError> {
+        let url = format!("{}/rpc/?v=5&type=search&by={}&arg={}", self.base_url, by.as_str(), encode_query(query));
+
+        let raw_response = self.get_text(&url).await?;
+        serde_json::from_str::<AurResponse>(&raw_response)
+            .map(|r| r.results)
+            .map_err(|e| aur_parse_error(e.to_string()))
+    }
+
+    async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError> {
+        let url = format!("{}/rpc/?v=5&type=info&arg={}", self.base_url, encode_query(package_name));
+
+        let mut aur_response: AurResponse = serde_json::from_str(&self.get_text(&url).await?)
             .map_err(|e| aur_parse_error(e.to_string()))?;
 
         aur_response.results.pop()
             .ok_or_else(|| AurError::NotFound(package_name.to_string()))
     }
+
+    /// Fetches info for several packages in a single multi-info RPC request
+    async fn get_packages_info(&self, package_names: &[String]) -> Result<Vec<AurPackage>, AurError> {
+        if package_names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let args: String = package_names.iter()
+            .map(|name| format!("&arg[]={}", encode_query(name)))
+            .collect();
+        let url = format!("{}/rpc/?v=5&type=info{}", self.base_url, args);
+
+        let aur_response: AurResponse = serde_json::from_str(&self.get_text(&url).await?)
+            .map_err(|e| aur_parse_error(e.to_string()))?;
+
+        Ok(aur_response.results)
+    }
+
+    /// Fetches one file of a pkgbase's git repo through the cgit plain endpoint
+    async fn get_repo_file(&self, pkgbase: &str, file: &str) -> Result<String, AurError> {
+        let url = format!("{}/cgit/aur.git/plain/{}?h={}", self.base_url, file, encode_query(pkgbase));
+
+        match self.get_text(&url).await {
+            Err(AurError::NotFound(_)) => Err(AurError::NotFound(format!("{} in {}", file, pkgbase))),
+            result => result,
+        }
+    }
+
+    fn clone_repo(&self, pkgbase: &str, dest: &Path) -> Result<bool, BuildError> {
+        PackageSource::aur(&self.base_url).fetch(pkgbase, dest)
+    }
 }
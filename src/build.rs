@@ -1,40 +1,317 @@
-use crate::error::{BuildError, build_git_error, build_makepkg_error};
+use crate::error::{AurError, BuildError, build_git_error, build_makepkg_error};
 use git2::Repository;
-use std::process::{Command, Stdio};
 use std::path::{Path, PathBuf};
 use std::{str, fs};
 use colored::Colorize;
-use crate::config::AppConfig;
+use log::info;
+use serde::Serialize;
+use crate::config::{AppConfig, PackageOverride, expand_home};
 use crate::alpm::AlpmWrapper;
 use crate::AlpmError;
+use crate::srcinfo::SrcInfo;
+use crate::exec::{CommandRunner, Invocation, OutputMode};
+use crate::aur::AurBackend;
+use crate::network;
+use crate::progress;
+use crate::sources::{AUR_SOURCE_NAME, PackageSource, SourceKind};
 
 pub struct PackageBuilder;
 
+/// A package found in the cache directory, with its debug package if one is cached
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CachedPackage {
+    pub name: String,
+    /// The pkgbase it was built from, which names the debug package
+    pub pkgbase: String,
+    pub version: String,
+    pub debug_version: Option<String>,
+}
+
+/// Source reported for packages reused from the cache
+pub const CACHE_SOURCE: &str = "cache";
+/// Source reported for packages built from a local PKGBUILD directory
+pub const LOCAL_SOURCE: &str = "local";
+/// Source reported for a clone left by an earlier run whose remote matches no source
+pub const EXISTING_SOURCE: &str = "existing";
+
+/// A package file ready to install, and where its PKGBUILD came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuiltPackage {
+    pub name: String,
+    pub pkgbase: String,
+    /// Name of the package source, or `cache` and `local`
+    pub source: String,
+    pub path: PathBuf,
+}
+
+/// Result of building a set of targets. AUR dependencies are installed during
+/// the build, the targets are left to the caller.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BuildSummary {
+    pub targets: Vec<BuiltPackage>,
+    pub dependencies: Vec<BuiltPackage>,
+}
+
+impl BuildSummary {
+    /// Package files of the targets, ready for a single `pacman -U`
+    pub fn target_paths(&self) -> Vec<PathBuf> {
+        self.targets.iter().map(|pkg| pkg.path.clone()).collect()
+    }
+}
+
+/// How makepkg is run for a pkgbase
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MakepkgOptions {
+    /// Passed after lilac's own flags
+    pub args: Vec<String>,
+    /// makepkg.conf to use instead of the system one
+    pub config: Option<PathBuf>,
+    /// Parallel make jobs, passed as `MAKEFLAGS=-jN`
+    pub jobs: Option<u32>,
+    /// Answer makepkg's prompts, such as for installing dependencies, with their defaults
+    pub noconfirm: bool,
+    /// Environment variables, which win over `MAKEFLAGS` from `jobs`
+    pub env: Vec<(String, String)>,
+    /// Directory package files are written to (`PKGDEST`) instead of the makepkg.conf one
+    pub pkgdest: Option<PathBuf>,
+}
+
+impl MakepkgOptions {
+    /// `makepkg <flags>` in `dir`, followed by the configured options
+    pub fn invocation(&self, dir: &Path, flags: &[&str]) -> Invocation {
+        let mut invocation = Invocation::new("makepkg").current_dir(dir).args(flags);
+        if let Some(config) = &self.config {
+            invocation = invocation.arg("--config").arg(config);
+        }
+        invocation = invocation.args(&self.args);
+        if self.noconfirm {
+            invocation = invocation.arg("--noconfirm");
+        }
+        if let Some(jobs) = self.jobs
+            && !self.env.iter().any(|(key, _)| key == "MAKEFLAGS")
+        {
+            invocation = invocation.env("MAKEFLAGS", &format!("-j{}", jobs));
+        }
+        for (key, value) in &self.env {
+            invocation = invocation.env(key, value);
+        }
+        if let Some(pkgdest) = &self.pkgdest {
+            invocation = invocation.env("PKGDEST", &pkgdest.to_string_lossy());
+        }
+        invocation
+    }
+}
+
+/// A pkgbase to build, along with the pkgnames that were requested from it
+struct BuildTarget {
+    pkgbase: String,
+    pkgnames: Vec<String>,
+    build_dir: PathBuf,
+    srcinfo: SrcInfo,
+    /// Package source the PKGBUILD came from
+    source: String,
+}
+
 impl PackageBuilder {
-    pub fn clone_repo(package_name: &str, dest_path: &Path) -> Result<(), BuildError> {
-        let url = format!("https://aur.archlinux.org/{}.git", package_name);
-        println!(
-            "{} {} {} {}",
-            "Cloning repository:".bold(),
-            package_name.bright_green(),
-            "to".bold(),
-            format!("{:?}", dest_path).bright_cyan()
-        );
+    /// Fetches the PKGBUILD repo of a pkgbase from the first configured source that
+    /// has it, and returns the name of that source. A source that fails, such as
+    /// an unreachable server, is reported and the next one is tried.
+    pub fn clone_repo(
+        package_name: &str,
+        dest_path: &Path,
+        aur: &dyn AurBackend,
+        config: &AppConfig
+    ) -> Result<String, BuildError> {
+        let mut failures: Vec<String> = Vec::new();
+        for source in config.package_sources() {
+            info!(
+                "{} {} {} {} {} {}",
+                "Cloning repository:".bold(),
+                package_name.bright_green(),
+                "from".bold(),
+                source.name.bright_magenta(),
+                "to".bold(),
+                format!("{:?}", dest_path).bright_cyan()
+            );
+
+            let fetched = if source.name == AUR_SOURCE_NAME {
+                aur.clone_repo(package_name, dest_path)
+            } else {
+                source.fetch(package_name, dest_path)
+            };
+            let found = match fetched {
+                Ok(found) => found,
+                Err(e) => {
+                    eprintln!(
+                        "{} {} {} {}: {}",
+                        "⚠ Warning:".yellow().bold(),
+                        package_name.bright_green(),
+                        "could not be fetched from".bold(),
+                        source.name.bright_magenta(),
+                        e
+                    );
+                    failures.push(format!("{}: {}", source.name, e));
+                    // Don't leave a partial clone for the next source to trip over
+                    if dest_path.exists() {
+                        let _ = fs::remove_dir_all(dest_path);
+                    }
+                    continue;
+                }
+            };
+            if found {
+                progress!(
+                    "{} {} {} {}",
+                    "Source:".bold(),
+                    package_name.bright_green(),
+                    "from".bold(),
+                    source.name.bright_magenta()
+                );
+                if let Some(package) = config.package_override(package_name) {
+                    Self::apply_override(package_name, dest_path, package)?;
+                }
+                return Ok(source.name);
+            }
+        }
 
-        Repository::clone(&url, dest_path)
-            .map_err(|e| build_git_error(
-                format!("Git clone failed: {}", e),
+        if failures.is_empty() {
+            Err(build_git_error("Not found in any package source", package_name))
+        } else {
+            Err(build_git_error(
+                format!("Not found in any package source that could be reached ({})", failures.join("; ")),
+                package_name
+            ))
+        }
+    }
+
+    /// Source an existing clone came from, going by its `origin` remote
+    fn clone_source(pkgbase: &str, repo_path: &Path, config: &AppConfig) -> String {
+        let url = Repository::open(repo_path).ok()
+            .and_then(|repo| repo.find_remote("origin").ok()?.url().map(str::to_string));
+        config.package_sources().into_iter()
+            .find(|source| url.as_deref() == Some(source.git_url(pkgbase).as_str()))
+            .map_or_else(|| EXISTING_SOURCE.to_string(), |source| source.name)
+    }
+
+    /// Checks out the pinned ref of a `[package.<name>]` table in a fresh clone,
+    /// then applies its patches to the working tree
+    pub fn apply_override(package_name: &str, repo_path: &Path, package: &PackageOverride) -> Result<(), BuildError> {
+        if let Some(git_ref) = &package.git_ref {
+            let git_error = |e: git2::Error| build_git_error(format!("Failed to check out {}: {}", git_ref, e), package_name);
+
+            let repo = Repository::open(repo_path).map_err(|_| build_git_error(
+                format!("Can't check out {}, the PKGBUILD source is not a git repo", git_ref),
                 package_name
             ))?;
+            // A fresh clone only has a local branch for the default one
+            let commit = repo.revparse_single(git_ref)
+                .or_else(|e| repo.revparse_single(&format!("origin/{}", git_ref)).map_err(|_| e))
+                .and_then(|object| object.peel_to_commit())
+                .map_err(git_error)?;
+            repo.checkout_tree(commit.as_object(), Some(git2::build::CheckoutBuilder::new().force()))
+                .map_err(git_error)?;
+            repo.set_head_detached(commit.id()).map_err(git_error)?;
+            progress!("{} {} {} {}", "Pinned:".bold(), package_name.bright_green(), "at".bold(), git_ref.bright_cyan());
+        }
+
+        if let Some(patch_dir) = &package.patch_dir {
+            let patch_dir = expand_home(patch_dir);
+            let mut patches: Vec<PathBuf> = fs::read_dir(&patch_dir)
+                .map_err(|e| build_git_error(format!("Failed to read patch dir {:?}: {}", patch_dir, e), package_name))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "patch" || ext == "diff"))
+                .collect();
+            patches.sort();
 
+            // Sources copied from a directory have no repo to apply patches in yet
+            let repo = Repository::open(repo_path)
+                .or_else(|_| Repository::init(repo_path))
+                .map_err(|e| build_git_error(format!("Failed to open {:?}: {}", repo_path, e), package_name))?;
+            for patch in &patches {
+                let name = patch.file_name().unwrap_or_default().to_string_lossy();
+                let content = fs::read(patch)
+                    .map_err(|e| build_git_error(format!("Failed to read patch {}: {}", name, e), package_name))?;
+                git2::Diff::from_buffer(&content)
+                    .and_then(|diff| repo.apply(&diff, git2::ApplyLocation::WorkDir, None))
+                    .map_err(|e| build_git_error(format!("Failed to apply patch {}: {}", name, e), package_name))?;
+                progress!("{} {} {} {}", "Patched:".bold(), package_name.bright_green(), "with".bold(), name.bright_cyan());
+            }
+        }
         Ok(())
     }
 
+    /// Fast-forwards an existing PKGBUILD clone to its remote. Returns false when it
+    /// was already up to date.
+    pub fn update_repo(package_name: &str, repo_path: &Path) -> Result<bool, BuildError> {
+        let git_error = |e: git2::Error| build_git_error(format!("Git update failed: {}", e), package_name);
+
+        let repo = Repository::open(repo_path).map_err(git_error)?;
+        let head = repo.head().map_err(git_error)?;
+        let branch = head.shorthand().unwrap_or("master").to_string();
+        repo.find_remote("origin")
+            .and_then(|mut remote| remote.fetch(&[&branch], Some(&mut network::fetch_options()), None))
+            .map_err(git_error)?;
+
+        let fetch_head = repo.find_reference("FETCH_HEAD").map_err(git_error)?;
+        let fetched = repo.reference_to_annotated_commit(&fetch_head).map_err(git_error)?;
+        let (analysis, _) = repo.merge_analysis(&[&fetched]).map_err(git_error)?;
+        if analysis.is_up_to_date() {
+            return Ok(false);
+        }
+        if !analysis.is_fast_forward() {
+            return Err(build_git_error("Local commits diverge from the remote, not updating", package_name));
+        }
+
+        // A safe checkout refuses to overwrite local edits to the PKGBUILD
+        let target = repo.find_object(fetched.id(), None).map_err(git_error)?;
+        repo.checkout_tree(&target, Some(git2::build::CheckoutBuilder::new().safe()))
+            .map_err(git_error)?;
+        repo.head().and_then(|mut head| head.set_target(fetched.id(), "lilac: fast-forward"))
+            .map_err(git_error)?;
+        Ok(true)
+    }
+
+    /// Reads every file of a PKGBUILD repo with a shallow fetch, without checking it
+    /// out. Returns (path, contents) pairs.
+    pub fn fetch_repo_files(package_name: &str, url: &str) -> Result<Vec<(String, Vec<u8>)>, BuildError> {
+        let git_error = |e: git2::Error| build_git_error(format!("Git fetch failed: {}", e), package_name);
+
+        let temp_dir = tempfile::tempdir().map_err(|e| build_git_error(
+            format!("Failed to create temp dir: {}", e),
+            package_name
+        ))?;
+        let repo = Repository::init_bare(temp_dir.path()).map_err(git_error)?;
+        let mut fetch_options = network::fetch_options();
+        // libgit2's local transport can't do shallow fetches
+        if !url.starts_with("file://") {
+            fetch_options.depth(1);
+        }
+        repo.remote_anonymous(url)
+            .and_then(|mut remote| remote.fetch(&["HEAD"], Some(&mut fetch_options), None))
+            .map_err(git_error)?;
+
+        let tree = repo.find_reference("FETCH_HEAD")
+            .and_then(|fetch_head| fetch_head.peel_to_tree())
+            .map_err(git_error)?;
+        let mut files = Vec::new();
+        tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if let Some(name) = entry.name()
+                && let Ok(blob) = entry.to_object(&repo).and_then(|object| object.peel_to_blob())
+            {
+                files.push((format!("{}{}", root, name), blob.content().to_vec()));
+            }
+            git2::TreeWalkResult::Ok
+        }).map_err(git_error)?;
+
+        Ok(files)
+    }
+
     pub fn execute_makepkg(
         package_name: &str,
         build_dir: &Path,
+        options: &MakepkgOptions,
+        runner: &dyn CommandRunner,
     ) -> Result<(), BuildError> {
-        println!(
+        progress!(
             "{} {} {} {}",
             "Running makepkg for".bold(),
             package_name.bright_green(),
@@ -42,89 +319,245 @@ impl PackageBuilder {
             format!("{:?}", build_dir).bright_cyan()
         );
 
-        let status = Command::new("makepkg")
-            .current_dir(build_dir)
-            .args(["--syncdeps", "--cleanbuild"])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
+        let status = runner.run(&options.invocation(build_dir, &["--syncdeps", "--cleanbuild"])
+            .output(OutputMode::Null))
             .map_err(|e| build_makepkg_error(
                 format!("Failed to spawn makepkg: {}", e),
                 "build"
             ))?;
 
-        if !status.success() {
+        if !status.success {
             return Err(build_makepkg_error(
                 format!("makepkg failed with exit code: {}", status),
                 "build"
             ));
         }
 
-        println!("\n{}\n", "✓ makepkg build succeeded.".green().bold());
+        if !runner.is_dry_run() {
+            progress!("\n{}\n", "✓ makepkg build succeeded.".green().bold());
+        }
         Ok(())
     }
 
-    pub fn get_dependencies_from_srcinfo(build_dir: &Path) -> Result<Vec<String>, BuildError> {
-        println!(
-            "{} {}",
-            "Extracting dependencies from .SRCINFO in:".bold(),
-            format!("{:?}", build_dir).bright_cyan()
-        );
+    /// Prints the steps fetching a pkgbase would take in dry-run mode
+    fn announce_clone(pkgbase: &str, fetch: &str, config: &AppConfig, runner: &dyn CommandRunner) {
+        runner.announce(fetch);
+        if let Some(package) = config.package_override(pkgbase) {
+            if let Some(git_ref) = &package.git_ref {
+                runner.announce(&format!("git checkout {}", git_ref));
+            }
+            if let Some(patch_dir) = &package.patch_dir {
+                runner.announce(&format!("git apply {}/*.patch", expand_home(patch_dir).display()));
+            }
+        }
+    }
 
-        let srcinfo_path = build_dir.join(".SRCINFO");
+    /// The .SRCINFO a source has for a pkgbase, read without cloning it, and the
+    /// step a real run would take to fetch it. None when the source doesn't have it.
+    async fn peek_source(
+        source: &PackageSource,
+        pkgbase: &str,
+        dest: &Path,
+        aur: &dyn AurBackend,
+    ) -> Result<Option<(String, String)>, BuildError> {
+        if source.name == AUR_SOURCE_NAME {
+            return match aur.get_repo_file(pkgbase, ".SRCINFO").await {
+                Ok(srcinfo) => Ok(Some((srcinfo, format!("git clone {} {}", source.git_url(pkgbase), dest.display())))),
+                Err(AurError::NotFound(_)) => Ok(None),
+                Err(e) => Err(build_git_error(format!("Failed to fetch .SRCINFO: {}", e), pkgbase)),
+            };
+        }
 
-        if !srcinfo_path.exists() {
-            return Err(build_makepkg_error(
-                format!(".SRCINFO file not found at {:?}", srcinfo_path),
-                "dependency extraction"
-            ));
+        match source.kind {
+            SourceKind::Git => {
+                let url = source.git_url(pkgbase);
+                // Like a clone, other servers answer unknown repos with an error
+                let Ok(files) = Self::fetch_repo_files(pkgbase, &url) else {
+                    return Ok(None);
+                };
+                if !files.iter().any(|(path, _)| path == "PKGBUILD") {
+                    return Ok(None);
+                }
+                let srcinfo = files.into_iter().find(|(path, _)| path == ".SRCINFO")
+                    .ok_or_else(|| build_git_error(format!("{} has no .SRCINFO", url), pkgbase))?;
+                Ok(Some((
+                    String::from_utf8_lossy(&srcinfo.1).into_owned(),
+                    format!("git clone {} {}", url, dest.display())
+                )))
+            }
+            SourceKind::Dir => {
+                let source_dir = PathBuf::from(&source.url).join(pkgbase);
+                if !source_dir.join("PKGBUILD").is_file() {
+                    return Ok(None);
+                }
+                let srcinfo = fs::read_to_string(source_dir.join(".SRCINFO")).map_err(|e| build_makepkg_error(
+                    format!("Failed to read .SRCINFO in {:?}: {}", source_dir, e),
+                    "fetch"
+                ))?;
+                Ok(Some((srcinfo, format!("cp -r {} {}", source_dir.display(), dest.display()))))
+            }
         }
+    }
 
-        let content = fs::read_to_string(&srcinfo_path)
-            .map_err(|e| build_makepkg_error(
-                format!("Failed to read .SRCINFO file: {}", e),
-                "dependency extraction"
-            ))?;
+    /// Clones a pkgbase to build it and returns the source it came from. In dry-run
+    /// mode the sources are tried in the same order, but only the .SRCINFO of the
+    /// one that has the pkgbase is fetched so resolution can carry on.
+    async fn fetch_pkgbuild(
+        pkgbase: &str,
+        dest: &Path,
+        aur: &dyn AurBackend,
+        config: &AppConfig,
+        runner: &dyn CommandRunner,
+    ) -> Result<String, BuildError> {
+        let source = if runner.is_dry_run() {
+            Self::plan_fetch(pkgbase, dest, aur, config, runner).await?
+        } else {
+            Self::clone_repo(pkgbase, dest, aur, config)?
+        };
 
-        let mut dependencies = Vec::new();
-
-        for line in content.lines() {
-            let trimmed = line.trim();
-            if trimmed.starts_with("depends =") || 
-               trimmed.starts_with("makedepends =") || 
-               trimmed.starts_with("checkdepends =") {
-                if let Some(dep) = trimmed.splitn(2, '=').nth(1) {
-                    let dep = dep.trim();
-                    if !dep.is_empty() {
-                        let pkg_name = dep.split(&['<', '>', '=', ' '][..])
-                            .next()
-                            .unwrap_or(dep)
-                            .trim()
-                            .to_string();
-                        if !pkg_name.is_empty() && !dependencies.contains(&pkg_name) {
-                            dependencies.push(pkg_name);
-                        }
-                    }
+        // Patches can change the version or dependencies the .SRCINFO lists
+        if config.package_override(pkgbase).is_some_and(|package| package.patch_dir.is_some()) {
+            SrcInfo::generate(dest, runner)?;
+        }
+        Ok(source)
+    }
+
+    /// The dry-run side of `fetch_pkgbuild`, which prints the fetch and writes
+    /// only the .SRCINFO of the source that has the pkgbase
+    async fn plan_fetch(
+        pkgbase: &str,
+        dest: &Path,
+        aur: &dyn AurBackend,
+        config: &AppConfig,
+        runner: &dyn CommandRunner,
+    ) -> Result<String, BuildError> {
+        let (source, srcinfo) = Self::plan_clone(pkgbase, dest, aur, config, runner).await?;
+        fs::create_dir_all(dest)
+            .and_then(|_| fs::write(dest.join(".SRCINFO"), srcinfo))
+            .map_err(|e| build_git_error(format!("Failed to write .SRCINFO: {}", e), pkgbase))?;
+        Ok(source)
+    }
+
+    /// Prints the clone `clone_repo` would make into `dest` without writing anything,
+    /// and returns the source that has the pkgbase along with its .SRCINFO
+    pub async fn plan_clone(
+        pkgbase: &str,
+        dest: &Path,
+        aur: &dyn AurBackend,
+        config: &AppConfig,
+        runner: &dyn CommandRunner,
+    ) -> Result<(String, String), BuildError> {
+        let mut failures: Vec<String> = Vec::new();
+        for source in config.package_sources() {
+            let (srcinfo, fetch) = match Self::peek_source(&source, pkgbase, dest, aur).await {
+                Ok(Some(found)) => found,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!(
+                        "{} {} {} {}: {}",
+                        "⚠ Warning:".yellow().bold(),
+                        pkgbase.bright_green(),
+                        "could not be fetched from".bold(),
+                        source.name.bright_magenta(),
+                        e
+                    );
+                    failures.push(format!("{}: {}", source.name, e));
+                    continue;
                 }
+            };
+            Self::announce_clone(pkgbase, &fetch, config, runner);
+            progress!(
+                "{} {} {} {}",
+                "Source:".bold(),
+                pkgbase.bright_green(),
+                "from".bold(),
+                source.name.bright_magenta()
+            );
+            return Ok((source.name, srcinfo));
+        }
+
+        if failures.is_empty() {
+            Err(build_git_error("Not found in any package source", pkgbase))
+        } else {
+            Err(build_git_error(
+                format!("Not found in any package source that could be reached ({})", failures.join("; ")),
+                pkgbase
+            ))
+        }
+    }
+
+    /// Fetches the PKGBUILD of a pkgbase into its build directory and returns the
+    /// source it came from. A clone left there by an earlier run, as kept by
+    /// `build.clone_dir`, is fast-forwarded instead.
+    async fn prepare_build_dir(
+        pkgbase: &str,
+        build_dir: &Path,
+        aur: &dyn AurBackend,
+        config: &AppConfig,
+        runner: &dyn CommandRunner,
+    ) -> Result<String, BuildError> {
+        let is_empty = !build_dir.is_dir() || fs::read_dir(build_dir).map_err(|e| build_makepkg_error(
+            format!("Failed to read directory {:?}: {}", build_dir, e),
+            "dependency check"
+        ))?.next().is_none();
+
+        // A pinned or patched clone is fetched again, so patches apply to a clean tree
+        let changes_checkout = config.package_override(pkgbase).is_some_and(PackageOverride::changes_checkout);
+
+        if is_empty {
+            Self::fetch_pkgbuild(pkgbase, build_dir, aur, config, runner).await
+        } else if changes_checkout && runner.is_dry_run() {
+            runner.announce(&format!("rm -rf {}", build_dir.display()));
+            Self::fetch_pkgbuild(pkgbase, build_dir, aur, config, runner).await
+        } else if changes_checkout {
+            progress!("{} {} {}", "Recloning:".bold(), pkgbase.bright_green(), "to apply its ref and patches".bold());
+            fs::remove_dir_all(build_dir).map_err(|e| build_git_error(
+                format!("Failed to remove old clone {:?}: {}", build_dir, e),
+                pkgbase
+            ))?;
+            Self::fetch_pkgbuild(pkgbase, build_dir, aur, config, runner).await
+        } else if build_dir.join(".git").is_dir() && runner.is_dry_run() {
+            runner.announce(&format!("git pull {:?}", build_dir));
+            Ok(Self::clone_source(pkgbase, build_dir, config))
+        } else if build_dir.join(".git").is_dir() {
+            if Self::update_repo(pkgbase, build_dir)? {
+                progress!("{} {}", "Updated:".bold(), pkgbase.bright_green());
             }
+            Ok(Self::clone_source(pkgbase, build_dir, config))
+        } else {
+            progress!("{} {} already exists, skipping clone.", "Repository:".bold(), pkgbase.bright_green());
+            Ok(EXISTING_SOURCE.to_string())
         }
+    }
 
-        Ok(dependencies)
+    /// Cache path a package would have once built, used in dry-run mode where nothing is built
+    fn planned_package(cache_dir: &Path, pkgname: &str, srcinfo: &SrcInfo) -> PathBuf {
+        cache_dir.join(format!("{}-{}-{}.pkg.tar.zst", pkgname, srcinfo.version(), std::env::consts::ARCH))
+    }
+
+    pub fn get_dependencies_from_srcinfo(build_dir: &Path) -> Result<Vec<String>, BuildError> {
+        info!(
+            "{} {}",
+            "Extracting dependencies from .SRCINFO in:".bold(),
+            format!("{:?}", build_dir).bright_cyan()
+        );
+
+        Ok(SrcInfo::from_dir(build_dir)?.all_dependencies())
     }
 
     pub async fn install_dependencies(
         dependencies: &[String],
         alpm: &AlpmWrapper,
-        aur: &crate::aur::AurClient,
+        aur: &dyn AurBackend,
         config: &AppConfig,
-    ) -> Result<(Vec<String>, Vec<std::path::PathBuf>), BuildError> {
+    ) -> Result<(Vec<String>, Vec<BuiltPackage>), BuildError> {
         let cache_dir = config.cache_path()?;
+        let runner = alpm.runner();
         let mut official_repo_deps: Vec<String> = Vec::new();
-        let mut aur_deps_to_build: Vec<String> = Vec::new();
-        let mut cached_deps_to_install: Vec<String> = Vec::new();
-        let mut cached_pkg_paths: Vec<std::path::PathBuf> = Vec::new();
+        let mut aur_deps_to_build: Vec<(String, String)> = Vec::new();
+        let mut cached_pkgs: Vec<BuiltPackage> = Vec::new();
 
-        println!("{}", "Categorizing dependencies...".bold());
+        progress!("{}", "Categorizing dependencies...".bold());
 
         for dep in dependencies.iter() {
             match alpm.is_package_installed(dep) {
@@ -148,12 +581,16 @@ impl PackageBuilder {
                  }
                  Ok(false) => {
                     if let Some(cached_pkg_path) = Self::find_cached_package(&cache_dir, dep) {
-                        cached_deps_to_install.push(dep.clone());
-                        cached_pkg_paths.push(cached_pkg_path);
+                        cached_pkgs.push(Self::cached_build(&cache_dir, dep, cached_pkg_path));
                     } else {
                          match aur.get_package_info(dep).await {
-                             Ok(_) => {
-                                 aur_deps_to_build.push(dep.clone());
+                             Ok(info) => {
+                                 let pkgbase = info.package_base.unwrap_or_else(|| dep.clone());
+                                 aur_deps_to_build.push((dep.clone(), pkgbase));
+                             },
+                             // Configured sources are keyed by pkgbase, clone_repo looks it up there
+                             Err(crate::error::AurError::NotFound(_)) if config.has_extra_sources() => {
+                                 aur_deps_to_build.push((dep.clone(), dep.clone()));
                              },
                              Err(crate::error::AurError::NotFound(_)) => {
                                  return Err(build_makepkg_error(
@@ -181,9 +618,8 @@ impl PackageBuilder {
 
         // Build and cache AUR dependencies
         if !aur_deps_to_build.is_empty() {
-            for dep in &aur_deps_to_build {
-                let current_alpm = AlpmWrapper::new()?;
-                match current_alpm.is_package_installed(&dep) {
+            for (dep, pkgbase) in &aur_deps_to_build {
+                match alpm.is_package_installed(dep) {
                     Ok(true) => {
                         continue;
                     },
@@ -196,25 +632,35 @@ impl PackageBuilder {
                     }
                 }
 
-                // Build from AUR
-                let temp_dir = tempfile::tempdir().map_err(|e| build_makepkg_error(
-                    format!("Failed to create temp dir for {}: {}", dep, e),
-                    "dependency resolution"
-                ))?;
+                // Another package from the same pkgbase may have built it already
+                if let Some(cached_path) = Self::find_cached_package(&cache_dir, dep) {
+                    let source = cached_pkgs.iter()
+                        .find(|pkg| pkg.pkgbase == *pkgbase)
+                        .map_or_else(|| CACHE_SOURCE.to_string(), |pkg| pkg.source.clone());
+                    cached_pkgs.push(BuiltPackage { name: dep.clone(), pkgbase: pkgbase.clone(), source, path: cached_path });
+                    continue;
+                }
 
-                let dep_build_dir = temp_dir.path().join(&dep);
-                Self::clone_repo(&dep, &dep_build_dir)?;
+                // Build from AUR
+                let dep_build_dir = config.build_path()?.join(pkgbase);
+                let source = Self::prepare_build_dir(pkgbase, &dep_build_dir, aur, config, runner).await?;
+                let built = |path: PathBuf| BuiltPackage {
+                    name: dep.clone(),
+                    pkgbase: pkgbase.clone(),
+                    source: source.clone(),
+                    path,
+                };
 
-                let output = std::process::Command::new("makepkg")
-                    .current_dir(&dep_build_dir)
-                    .args(["--syncdeps"])
-                    .output()
+                let options = Self::build_options(pkgbase, config, runner)?;
+                let output = runner.run(&options
+                    .invocation(&dep_build_dir, &["--syncdeps"])
+                    .output(OutputMode::Capture))
                     .map_err(|e| build_makepkg_error(
                         format!("makepkg failed for dependency {}: {}", dep, e),
                         "dependency build"
                     ))?;
 
-                if !output.status.success() {
+                if !output.success {
                     return Err(build_makepkg_error(
                         format!("Failed to build dependency {}: {}", dep,
                             std::str::from_utf8(&output.stderr).unwrap_or("<invalid UTF-8>")),
@@ -222,94 +668,229 @@ impl PackageBuilder {
                     ));
                 }
 
-                let pkg_path_in_temp = Self::find_built_package(&dep_build_dir, &dep)?;
-                Self::cache_package(&pkg_path_in_temp, &cache_dir, &dep)?;
-                let cached_path = cache_dir.join(pkg_path_in_temp.file_name().unwrap());
-                if cached_path.exists() {
-                    cached_pkg_paths.push(cached_path);
-                } else {
-                    return Err(build_makepkg_error(
-                        format!("Failed to find cached package {} in cache after building and caching", dep),
-                        "caching",
-                    ));
+                if runner.is_dry_run() {
+                    let srcinfo = SrcInfo::from_dir(&dep_build_dir)?;
+                    cached_pkgs.push(built(Self::planned_package(&cache_dir, dep, &srcinfo)));
+                    continue;
                 }
-            }
-        }
 
-        // Add any cached dependencies
-        for cached_pkg_path in &cached_pkg_paths {
-            if !official_repo_deps.contains(&cached_pkg_path.to_string_lossy().to_string()) {
-                // Only add unique paths, I dont like this
-                // TODO: fix my own stupidity
+                Self::cache_built_packages(&dep_build_dir, &options, &cache_dir, config.keep_debug_packages)?;
+                match Self::find_cached_package(&cache_dir, dep) {
+                    Some(cached_path) => cached_pkgs.push(built(cached_path)),
+                    None => {
+                        return Err(build_makepkg_error(
+                            format!("Failed to find cached package {} in cache after building and caching", dep),
+                            "caching",
+                        ));
+                    }
+                }
             }
         }
 
-        Ok((official_repo_deps, cached_pkg_paths))
+        Ok((official_repo_deps, cached_pkgs))
     }
 
-    pub async fn build_package_with_deps(
-        package_name: &str,
-        build_dir: &Path,
-        aur: &crate::aur::AurClient,
+    /// Builds a set of target packages in one pass. Dependencies of every target are
+    /// resolved together, so shared dependencies are only categorized, built and
+    /// installed once. Targets are mapped to their pkgbase, so split packages from
+    /// the same PKGBUILD are built once. Cached artifacts are reused, except for the
+    /// targets listed in `rebuild`. Returns the artifacts of the targets, ready to be
+    /// installed in a single transaction, and the AUR dependencies installed on the way.
+    pub async fn build_packages_with_deps(
+        packages: &[String],
+        rebuild: &[String],
+        aur: &dyn AurBackend,
+        alpm: &AlpmWrapper,
         config: &AppConfig,
-    ) -> Result<Vec<PathBuf>, BuildError> {
+    ) -> Result<BuildSummary, BuildError> {
         let cache_dir = config.cache_path().map_err(|e| build_makepkg_error(
             format!("Failed to access cache directory: {}", e),
             "caching",
         ))?;
 
-        if let Some(cached_pkg) = Self::find_cached_package(&cache_dir, package_name) {
-            println!(
-                "{} {} {}",
-                "Using cached package:".bold(),
-                package_name.bright_green(),
-                format!("({:?})", cached_pkg).bright_cyan()
+        let mut target_pkgs: Vec<BuiltPackage> = Vec::new();
+        let mut uncached: Vec<String> = Vec::new();
+        let mut all_dependencies: Vec<String> = Vec::new();
+
+        for package_name in packages {
+            if !rebuild.contains(package_name)
+                && let Some(cached_pkg) = Self::find_cached_package(&cache_dir, package_name)
+            {
+                progress!(
+                    "{} {} {}",
+                    "Using cached package:".bold(),
+                    package_name.bright_green(),
+                    format!("({:?})", cached_pkg).bright_cyan()
+                );
+                let deps = Self::read_dependency_list(package_name, &cache_dir).unwrap_or_default();
+                all_dependencies.extend(deps);
+                target_pkgs.push(Self::cached_build(&cache_dir, package_name, cached_pkg));
+                let pkgbase = Self::cached_pkgbase(&cache_dir, package_name);
+                let debug_name = Self::debug_package_name(&pkgbase);
+                if config.keep_debug_packages
+                    && let Some(debug_pkg) = Self::find_cached_package(&cache_dir, &debug_name)
+                    && !target_pkgs.iter().any(|pkg| pkg.path == debug_pkg)
+                {
+                    target_pkgs.push(BuiltPackage { name: debug_name, pkgbase, source: CACHE_SOURCE.to_string(), path: debug_pkg });
+                }
+            } else {
+                uncached.push(package_name.clone());
+            }
+        }
+
+        let build_path = config.build_path()?;
+
+        // Map every requested pkgname to its pkgbase so split packages share one build
+        let aur_infos = aur.get_packages_info(&uncached).await.map_err(|e| build_makepkg_error(
+            format!("Failed to fetch AUR info for {:?}: {}", uncached, e),
+            "dependency resolution",
+        ))?;
+        let mut to_build: Vec<BuildTarget> = Vec::new();
+        for package_name in &uncached {
+            let pkgbase = match aur_infos.iter().find(|info| info.name == *package_name) {
+                Some(info) => info.package_base.clone().unwrap_or_else(|| package_name.clone()),
+                // Packages outside the AUR may still come from a configured source
+                None if config.has_extra_sources() => package_name.clone(),
+                None => {
+                    return Err(build_makepkg_error(
+                        format!("Package {} not found in the AUR", package_name),
+                        "dependency resolution",
+                    ));
+                }
+            };
+            match to_build.iter_mut().find(|target| target.pkgbase == pkgbase) {
+                Some(target) => target.pkgnames.push(package_name.clone()),
+                None => to_build.push(BuildTarget {
+                    build_dir: build_path.join(&pkgbase),
+                    pkgbase,
+                    pkgnames: vec![package_name.clone()],
+                    srcinfo: SrcInfo::default(),
+                    source: String::new(),
+                }),
+            }
+        }
+
+        for target in &mut to_build {
+            progress!(
+                "{} {} {} {}",
+                "Building package".bold(),
+                target.pkgbase.bright_green(),
+                "in:".bold(),
+                format!("{:?}", target.build_dir).bright_cyan()
             );
-            let mut pkgs = vec![];
-            let deps = Self::read_dependency_list(package_name, &cache_dir).unwrap_or_default();
-            for dep in deps {
-                if let Some(dep_pkg) = Self::find_cached_package(&cache_dir, &dep) {
-                    pkgs.push(dep_pkg);
+
+            target.source = Self::prepare_build_dir(&target.pkgbase, &target.build_dir, aur, config, alpm.runner()).await?;
+
+            target.srcinfo = SrcInfo::from_dir(&target.build_dir)?;
+
+            // Sibling packages a requested package depends on are installed alongside it
+            let siblings = target.srcinfo.pkgnames();
+            let mut i = 0;
+            while i < target.pkgnames.len() {
+                for dep in target.srcinfo.package_depends(&target.pkgnames[i]) {
+                    if siblings.contains(&dep)
+                        && !target.pkgnames.contains(&dep)
+                        && !alpm.is_package_installed(&dep).unwrap_or(false)
+                    {
+                        target.pkgnames.push(dep);
+                    }
                 }
+                i += 1;
             }
-            pkgs.push(cached_pkg);
-            return Ok(pkgs);
+            all_dependencies.extend(target.srcinfo.dependencies_for(&target.pkgnames));
         }
 
-        println!(
-            "{} {} {} {}",
-            "Building package".bold(),
-            package_name.bright_green(),
-            "in:".bold(),
-            format!("{:?}", build_dir).bright_cyan()
-        );
+        Self::build_targets(to_build, all_dependencies, target_pkgs, aur, alpm, config).await
+    }
 
-        if !build_dir.exists() || !build_dir.is_dir() || std::fs::read_dir(build_dir).map_err(|e| build_makepkg_error(
-            format!("Failed to read directory {:?}: {}", build_dir, e),
-            "dependency check"
-        ))?.count() == 0 {
-            Self::clone_repo(package_name, build_dir)?;
-        } else {
-            println!("{} {} already exists, skipping clone.", "Repository:".bold(), package_name.bright_green());
+    /// Builds local PKGBUILD directories with the same pipeline as AUR packages. The
+    /// .SRCINFO is regenerated when it is missing or older than the PKGBUILD, every
+    /// package of each pkgbase is built in place and cached, and the artifacts are
+    /// returned ready to be installed.
+    pub async fn build_local_packages(
+        dirs: &[PathBuf],
+        aur: &dyn AurBackend,
+        alpm: &AlpmWrapper,
+        config: &AppConfig,
+    ) -> Result<BuildSummary, BuildError> {
+        let mut to_build: Vec<BuildTarget> = Vec::new();
+        let mut all_dependencies: Vec<String> = Vec::new();
+
+        for dir in dirs {
+            let build_dir = fs::canonicalize(dir).map_err(|e| build_makepkg_error(
+                format!("Failed to access {:?}: {}", dir, e),
+                "dependency resolution",
+            ))?;
+            if !build_dir.join("PKGBUILD").is_file() {
+                return Err(build_makepkg_error(
+                    format!("No PKGBUILD found in {:?}", build_dir),
+                    "dependency resolution",
+                ));
+            }
+
+            let srcinfo = SrcInfo::load_or_generate(&build_dir, alpm.runner())?;
+            progress!(
+                "{} {} {} {}",
+                "Building local package".bold(),
+                srcinfo.pkgbase.bright_green(),
+                "in:".bold(),
+                format!("{:?}", build_dir).bright_cyan()
+            );
+            let pkgnames = srcinfo.pkgnames();
+            all_dependencies.extend(srcinfo.dependencies_for(&pkgnames));
+            to_build.push(BuildTarget {
+                pkgbase: srcinfo.pkgbase.clone(),
+                pkgnames,
+                build_dir,
+                srcinfo,
+                source: LOCAL_SOURCE.to_string(),
+            });
         }
 
-        let dependencies = Self::get_dependencies_from_srcinfo(build_dir)?;
-        
-        let alpm = AlpmWrapper::new()?;
-        let (official_repo_deps, aur_pkg_paths) = Self::install_dependencies(&dependencies, &alpm, aur, config).await?;
+        Self::build_targets(to_build, all_dependencies, Vec::new(), aur, alpm, config).await
+    }
+
+    /// Installs the dependencies of every target, then builds and caches each pkgbase
+    /// once. The artifacts of the requested pkgnames are appended to `target_pkgs`.
+    /// Targets that depend on each other are built in dependency order, and each is
+    /// installed before the targets that need it are built.
+    async fn build_targets(
+        to_build: Vec<BuildTarget>,
+        all_dependencies: Vec<String>,
+        mut target_pkgs: Vec<BuiltPackage>,
+        aur: &dyn AurBackend,
+        alpm: &AlpmWrapper,
+        config: &AppConfig,
+    ) -> Result<BuildSummary, BuildError> {
+        let cache_dir = config.cache_path().map_err(|e| build_makepkg_error(
+            format!("Failed to access cache directory: {}", e),
+            "caching",
+        ))?;
+
+        // Dependencies on another target are met by building that target first
+        let to_build = Self::order_targets(to_build)?;
+        let mut shared_deps: Vec<String> = Vec::new();
+        for dep in all_dependencies {
+            let is_pending_target = to_build.iter().any(|target| target.srcinfo.pkgnames().contains(&dep));
+            if !shared_deps.contains(&dep) && !is_pending_target {
+                shared_deps.push(dep);
+            }
+        }
+
+        let (official_repo_deps, aur_deps) = Self::install_dependencies(&shared_deps, alpm, aur, config).await?;
+        let aur_pkg_paths: Vec<PathBuf> = aur_deps.iter().map(|pkg| pkg.path.clone()).collect();
+        let runner = alpm.runner();
 
         // Install official repo dependencies with pacman -S --needed
         if !official_repo_deps.is_empty() {
-            println!("\n{}\n", "✓ Official repository dependencies found.".green().bold());
-            let status = std::process::Command::new("sudo")
-                .arg("pacman")
+            progress!("\n{}\n", "✓ Official repository dependencies found.".green().bold());
+            let status = runner.run(&alpm.pacman_options().invocation()
                 .arg("-S")
                 .arg("--needed")
-                .args(&official_repo_deps)
-                .status();
+                .args(&official_repo_deps));
             match status {
                 Ok(exit_status) => {
-                    if !exit_status.success() {
+                    if !exit_status.success {
                         return Err(build_makepkg_error(
                             format!("pacman -S failed with exit code: {}", exit_status),
                             "dependency pre-install",
@@ -327,83 +908,282 @@ impl PackageBuilder {
 
         // Install AUR dependencies with pacman -U
         if !aur_pkg_paths.is_empty() {
-            println!("\n{}\n", "✓ AUR dependencies found.".green().bold());
-            let status = std::process::Command::new("sudo")
-                .arg("pacman")
-                .arg("-U")
-                .args(&aur_pkg_paths)
-                .status();
-            match status {
-                Ok(exit_status) => {
-                    if !exit_status.success() {
+            progress!("\n{}\n", "✓ AUR dependencies found.".green().bold());
+            Self::install_built_dependencies(&aur_pkg_paths, alpm)?;
+        }
+
+        let mut dependencies = aur_deps;
+        for (index, target) in to_build.iter().enumerate() {
+            let built = |name: &str, path: PathBuf| BuiltPackage {
+                name: name.to_string(),
+                pkgbase: target.pkgbase.clone(),
+                source: target.source.clone(),
+                path,
+            };
+            let package_path = |name: &str| if runner.is_dry_run() {
+                Some(Self::planned_package(&cache_dir, name, &target.srcinfo))
+            } else {
+                Self::find_cached_package(&cache_dir, name)
+            };
+
+            let options = Self::build_options(&target.pkgbase, config, runner)?;
+            Self::execute_makepkg(&target.pkgbase, &target.build_dir, &options, runner)?;
+            if !runner.is_dry_run() {
+                progress!("{} {} {}.", "Main package:".bold(), target.pkgbase.bright_green(), "built successfully".bold());
+
+                Self::cache_built_packages(&target.build_dir, &options, &cache_dir, config.keep_debug_packages)?;
+            }
+
+            // Only the requested pkgnames are installed, the rest of the split stays cached
+            for package_name in &target.pkgnames {
+                match package_path(package_name) {
+                    Some(main_pkg) => target_pkgs.push(built(package_name, main_pkg)),
+                    None => {
                         return Err(build_makepkg_error(
-                            format!("pacman -U failed with exit code: {}", exit_status),
-                            "dependency pre-install",
+                            format!("Failed to find any packages to install for {}", package_name),
+                            "caching"
                         ));
                     }
                 }
-                Err(e) => {
-                    return Err(build_makepkg_error(
-                        format!("Failed to execute pacman -U for AUR dependencies: {}", e),
-                        "dependency pre-install",
-                    ));
+            }
+
+            let debug_name = Self::debug_package_name(&target.pkgbase);
+            if !runner.is_dry_run()
+                && config.keep_debug_packages
+                && let Some(debug_pkg) = Self::find_cached_package(&cache_dir, &debug_name)
+            {
+                target_pkgs.push(built(&debug_name, debug_pkg));
+            }
+
+            // The targets built next may need packages of this one to build
+            let pkgnames = target.srcinfo.pkgnames();
+            let mut needed: Vec<String> = Vec::new();
+            for dep in to_build[index + 1..].iter().flat_map(|later| later.srcinfo.dependencies_for(&later.pkgnames)) {
+                if pkgnames.contains(&dep) && !needed.contains(&dep) {
+                    needed.push(dep);
+                }
+            }
+            let mut needed_paths: Vec<PathBuf> = Vec::new();
+            for name in &needed {
+                let path = package_path(name).ok_or_else(|| build_makepkg_error(
+                    format!("Failed to find cached package {} needed by another target", name),
+                    "caching",
+                ))?;
+                if !target.pkgnames.contains(name) {
+                    dependencies.push(built(name, path.clone()));
                 }
+                needed_paths.push(path);
+            }
+            if !needed_paths.is_empty() {
+                Self::install_built_dependencies(&needed_paths, alpm)?;
             }
         }
 
-        Self::execute_makepkg(package_name, build_dir)?;
-
-        println!("{} {} {}.", "Main package:".bold(), package_name.bright_green(), "built successfully".bold());
+        Ok(BuildSummary { targets: target_pkgs, dependencies })
+    }
 
-        let pkg_path_in_temp = Self::find_built_package(build_dir, package_name)?;
-        Self::cache_package(&pkg_path_in_temp, &cache_dir, package_name)?;
-        Self::save_dependency_list(package_name, &cache_dir, &dependencies)?;
+    /// Orders targets so that each comes after the targets whose packages it depends on
+    fn order_targets(mut pending: Vec<BuildTarget>) -> Result<Vec<BuildTarget>, BuildError> {
+        let mut ordered: Vec<BuildTarget> = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|target| {
+                let dependencies = target.srcinfo.dependencies_for(&target.pkgnames);
+                !pending.iter().any(|other| other.pkgbase != target.pkgbase
+                    && other.srcinfo.pkgnames().iter().any(|name| dependencies.contains(name)))
+            });
+            match ready {
+                Some(index) => ordered.push(pending.remove(index)),
+                None => {
+                    let pkgbases: Vec<&str> = pending.iter().map(|target| target.pkgbase.as_str()).collect();
+                    return Err(build_makepkg_error(
+                        format!("Dependency cycle between {}", pkgbases.join(", ")),
+                        "dependency resolution",
+                    ));
+                }
+            }
+        }
+        Ok(ordered)
+    }
 
-        // Collect all dependency package paths from install_dependencies, then add main package last
-        let mut all_pkgs = aur_pkg_paths;
-        if let Some(main_pkg) = Self::find_cached_package(&cache_dir, package_name) {
-            all_pkgs.push(main_pkg);
+    /// Installs built packages that the next builds depend on with pacman -U
+    fn install_built_dependencies(paths: &[PathBuf], alpm: &AlpmWrapper) -> Result<(), BuildError> {
+        let status = alpm.runner().run(&alpm.pacman_options().invocation()
+            .arg("-U")
+            .args(paths));
+        match status {
+            Ok(exit_status) if exit_status.success => Ok(()),
+            Ok(exit_status) => Err(build_makepkg_error(
+                format!("pacman -U failed with exit code: {}", exit_status),
+                "dependency pre-install",
+            )),
+            Err(e) => Err(build_makepkg_error(
+                format!("Failed to execute pacman -U for dependencies: {}", e),
+                "dependency pre-install",
+            )),
         }
-        if all_pkgs.is_empty() {
-            return Err(build_makepkg_error(
-                format!("Failed to find any packages to install for {}", package_name),
-                "caching"
-            ));
+    }
+
+    /// A package reused from the cache
+    fn cached_build(cache_dir: &Path, package_name: &str, path: PathBuf) -> BuiltPackage {
+        BuiltPackage {
+            name: package_name.to_string(),
+            pkgbase: Self::cached_pkgbase(cache_dir, package_name),
+            source: CACHE_SOURCE.to_string(),
+            path,
         }
-        Ok(all_pkgs)
     }
 
     pub fn find_cached_package(cache_dir: &Path, package_name: &str) -> Option<PathBuf> {
         let entries = fs::read_dir(cache_dir).ok()?;
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-                    if file_name.starts_with(package_name) 
-                        && (file_name.ends_with(".pkg.tar.zst") || file_name.ends_with(".pkg.tar.xz"))
-                    {
-                        return Some(path);
-                    }
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(file_name) = path.file_name().and_then(|f| f.to_str())
+                && Self::parse_package_filename(file_name).is_some_and(|(name, _, _)| name == package_name)
+            {
+                return Some(path);
             }
         }
         None
     }
 
-    fn cache_package(pkg_path: &Path, cache_dir: &Path, package_name: &str) -> Result<(), BuildError> {
-        let cached_path = cache_dir.join(pkg_path.file_name().unwrap());
-        fs::copy(pkg_path, &cached_path).map_err(|e| build_makepkg_error(
-            format!("Failed to cache package: {}", e),
-            "caching",
-        ))?;
+    /// makepkg options for building a pkgbase. Packages are written to an empty
+    /// directory of their own, so files left in a kept clone by earlier builds are
+    /// never mistaken for the new ones, and makepkg doesn't refuse to rebuild a
+    /// version it finds there.
+    fn build_options(pkgbase: &str, config: &AppConfig, runner: &dyn CommandRunner) -> Result<MakepkgOptions, BuildError> {
+        let pkgdest = config.temp_path().join("pkgdest").join(pkgbase);
+        if !runner.is_dry_run() {
+            if pkgdest.exists() {
+                fs::remove_dir_all(&pkgdest).map_err(|e| build_makepkg_error(
+                    format!("Failed to clear {:?}: {}", pkgdest, e),
+                    "build"
+                ))?;
+            }
+            fs::create_dir_all(&pkgdest).map_err(|e| build_makepkg_error(
+                format!("Failed to create {:?}: {}", pkgdest, e),
+                "build"
+            ))?;
+        }
+        Ok(MakepkgOptions { pkgdest: Some(pkgdest), ..config.makepkg_options(pkgbase) })
+    }
 
-        println!(
-            "{} {} {}",
-            "Cached package:".bold(),
-            package_name.bright_green(),
-            format!("({:?})", cached_path).bright_cyan()
-        );
-        Ok(())
+    /// Moves every package a build produced into the cache and records the dependency
+    /// list of each one. Split packages are cached even when only some were requested,
+    /// and the pkgbase's debug package is cached too when `keep_debug` is set.
+    fn cache_built_packages(
+        build_dir: &Path,
+        options: &MakepkgOptions,
+        cache_dir: &Path,
+        keep_debug: bool,
+    ) -> Result<Vec<PathBuf>, BuildError> {
+        let srcinfo = SrcInfo::from_dir(build_dir)?;
+        let pkgdest = options.pkgdest.as_deref().unwrap_or(build_dir);
+        let mut cached_paths = Vec::new();
+
+        for pkg_path in Self::find_built_packages(pkgdest, &srcinfo, keep_debug)? {
+            let file_name = pkg_path.file_name().unwrap();
+            let cached_path = cache_dir.join(file_name);
+
+            // Keep a single version of each package in the cache
+            if let Some((name, _, _)) = file_name.to_str().and_then(Self::parse_package_filename)
+                && Self::find_cached_package(cache_dir, &name).is_some_and(|old| old != cached_path)
+            {
+                Self::delete_cached_package(cache_dir, &name)?;
+            }
+
+            // The cache may be on another filesystem than the temp directory
+            fs::rename(&pkg_path, &cached_path)
+                .or_else(|_| fs::copy(&pkg_path, &cached_path).and_then(|_| fs::remove_file(&pkg_path)))
+                .map_err(|e| build_makepkg_error(
+                    format!("Failed to cache package: {}", e),
+                    "caching",
+                ))?;
+
+            progress!(
+                "{} {} {}",
+                "Cached package:".bold(),
+                srcinfo.pkgbase.bright_green(),
+                format!("({:?})", cached_path).bright_cyan()
+            );
+            cached_paths.push(cached_path);
+        }
+
+        for pkgname in srcinfo.pkgnames() {
+            let mut dependencies = srcinfo.package_depends(&pkgname);
+            for dep in srcinfo.makedepends.iter().chain(&srcinfo.checkdepends) {
+                if !dependencies.contains(dep) {
+                    dependencies.push(dep.clone());
+                }
+            }
+            Self::save_dependency_list(&pkgname, cache_dir, &dependencies)?;
+            Self::save_pkgbase(&pkgname, cache_dir, &srcinfo.pkgbase)?;
+        }
+
+        Ok(cached_paths)
+    }
+
+    /// Version of the cached package file for a pkgname, if one is cached
+    pub fn cached_version(cache_dir: &Path, package_name: &str) -> Option<String> {
+        let path = Self::find_cached_package(cache_dir, package_name)?;
+        let file_name = path.file_name()?.to_str()?;
+        Self::parse_package_filename(file_name).map(|(_, version, _)| version)
+    }
+
+    /// Lists every package in the cache directory, sorted by name. A debug package is
+    /// grouped under a cached package of its pkgbase: the one named after the pkgbase,
+    /// or else the first of its split packages.
+    pub fn list_cached_packages(cache_dir: &Path) -> Result<Vec<CachedPackage>, BuildError> {
+        let entries = fs::read_dir(cache_dir)
+            .map_err(|e| build_makepkg_error(
+                format!("Failed to read cache directory: {}", e),
+                "cache listing",
+            ))?;
+
+        let mut found: Vec<(String, String)> = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| build_makepkg_error(
+                format!("Error reading cache directory entry: {}", e),
+                "cache listing",
+            ))?;
+            let path = entry.path();
+            if let Some(file_name) = path.file_name().and_then(|f| f.to_str())
+                && (file_name.ends_with(".pkg.tar.zst") || file_name.ends_with(".pkg.tar.xz"))
+            {
+                found.push(match Self::parse_package_filename(file_name) {
+                    Some((name, version, _)) => (name, version),
+                    None => (file_name.to_string(), "unknown".to_string()),
+                });
+            }
+        }
+        found.sort();
+
+        let pkgbases: Vec<String> = found.iter().map(|(name, _)| Self::cached_pkgbase(cache_dir, name)).collect();
+        let is_grouped_debug = |name: &str| name.strip_suffix("-debug")
+            .is_some_and(|base| found.iter().zip(&pkgbases).any(|((other, _), pkgbase)| other != name && pkgbase == base));
+
+        let mut packages: Vec<CachedPackage> = Vec::new();
+        for ((name, version), pkgbase) in found.iter().zip(&pkgbases) {
+            if is_grouped_debug(name) {
+                continue;
+            }
+            let members: Vec<&String> = found.iter().zip(&pkgbases)
+                .filter(|((other, _), base)| *base == pkgbase && !is_grouped_debug(other))
+                .map(|((other, _), _)| other)
+                .collect();
+            let holds_debug = members.iter().find(|member| **member == pkgbase).or(members.first()) == Some(&name);
+
+            let debug_name = Self::debug_package_name(pkgbase);
+            packages.push(CachedPackage {
+                name: name.clone(),
+                pkgbase: pkgbase.clone(),
+                version: version.clone(),
+                debug_version: found.iter()
+                    .find(|(other, _)| holds_debug && *other == debug_name)
+                    .map(|(_, version)| version.clone()),
+            });
+        }
+
+        Ok(packages)
     }
 
     /// Deletes a package from the cache directory.
@@ -422,22 +1202,20 @@ impl PackageBuilder {
                 "cache cleanup",
             ))?;
             let path = entry.path();
-            if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-                if file_name.starts_with(package_name) 
-                    && (file_name.ends_with(".pkg.tar.zst") || file_name.ends_with(".pkg.tar.xz"))
-                {
-                    fs::remove_file(&path).map_err(|e| build_makepkg_error(
-                        format!("Failed to delete cached package: {}\n", e),
-                        "cache cleanup",
-                    ))?;
-                    println!(
-                        "{} {} {}",
-                        "Deleted cached package:".bold(),
-                        package_name.bright_green(),
-                        format!("({:?})", path).bright_cyan()
-                    );
-                    packages_info.push(file_name.to_string());
-                }
+            if let Some(file_name) = path.file_name().and_then(|f| f.to_str())
+                && Self::parse_package_filename(file_name).is_some_and(|(name, _, _)| name == package_name)
+            {
+                fs::remove_file(&path).map_err(|e| build_makepkg_error(
+                    format!("Failed to delete cached package: {}\n", e),
+                    "cache cleanup",
+                ))?;
+                progress!(
+                    "{} {} {}",
+                    "Deleted cached package:".bold(),
+                    package_name.bright_green(),
+                    format!("({:?})", path).bright_cyan()
+                );
+                packages_info.push(file_name.to_string());
             }
         }
 
@@ -458,7 +1236,7 @@ impl PackageBuilder {
             format!("Failed to write dependency list to {}: {}", deps_file_path.display(), e),
             "dependency tracking",
         ))?;
-        println!(
+        progress!(
             "{} {} {}",
             "Saved dependency list for:".bold(),
             package_name.bright_green(),
@@ -467,6 +1245,42 @@ impl PackageBuilder {
         Ok(())
     }
 
+    /// Records which pkgbase a cached package was built from
+    fn save_pkgbase(package_name: &str, cache_dir: &Path, pkgbase: &str) -> Result<(), BuildError> {
+        let pkgbase_file_path = cache_dir.join(format!("{}.lilac_pkgbase", package_name));
+        fs::write(&pkgbase_file_path, pkgbase).map_err(|e| build_makepkg_error(
+            format!("Failed to write pkgbase to {}: {}", pkgbase_file_path.display(), e),
+            "dependency tracking",
+        ))
+    }
+
+    /// The pkgbase a cached package was built from. Packages cached before it was
+    /// recorded count as their own pkgbase.
+    pub fn cached_pkgbase(cache_dir: &Path, package_name: &str) -> String {
+        fs::read_to_string(cache_dir.join(format!("{}.lilac_pkgbase", package_name)))
+            .ok()
+            .map(|content| content.trim().to_string())
+            .filter(|pkgbase| !pkgbase.is_empty())
+            .unwrap_or_else(|| package_name.to_string())
+    }
+
+    /// Cached packages built from a pkgbase
+    pub fn cached_pkgbase_members(cache_dir: &Path, pkgbase: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(cache_dir) else {
+            return Vec::new();
+        };
+        let mut members: Vec<String> = entries.flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_str()?.to_string();
+                let (name, _, _) = Self::parse_package_filename(&file_name)?;
+                (Self::cached_pkgbase(cache_dir, &name) == pkgbase).then_some(name)
+            })
+            .collect();
+        members.sort();
+        members.dedup();
+        members
+    }
+
     // function to read the list of dependencies for a package from the cache
     pub fn read_dependency_list(
         package_name: &str,
@@ -485,7 +1299,7 @@ impl PackageBuilder {
 
         let dependencies: Vec<String> = content.lines().map(|s| s.to_string()).collect();
 
-        println!(
+        info!(
             "{} {} {}",
             "Read dependency list for:".bold(),
             package_name.bright_green(),
@@ -495,32 +1309,80 @@ impl PackageBuilder {
         Ok(dependencies)
     }
 
-    fn find_built_package(build_dir: &Path, package_name: &str) -> Result<PathBuf, BuildError> {
+    /// Packages whose recorded dependency list contains a package
+    pub fn recorded_dependents(cache_dir: &Path, package_name: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(cache_dir) else {
+            return Vec::new();
+        };
+        let mut dependents: Vec<String> = entries.flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_str()?.to_string();
+                let pkgname = file_name.strip_suffix(".lilac_deps")?;
+                let content = fs::read_to_string(entry.path()).ok()?;
+                content.lines().any(|dep| dep == package_name).then(|| pkgname.to_string())
+            })
+            .collect();
+        dependents.sort();
+        dependents
+    }
+
+    /// Finds the package files a build produced for the pkgnames listed in its .SRCINFO.
+    /// The `<pkgbase>-debug` package is only included when `keep_debug` is set.
+    fn find_built_packages(build_dir: &Path, srcinfo: &SrcInfo, keep_debug: bool) -> Result<Vec<PathBuf>, BuildError> {
         let entries = fs::read_dir(build_dir)
             .map_err(|e| build_makepkg_error(
                 format!("Failed to read build directory: {}", e),
                 "package discovery"
             ))?;
 
+        let mut pkgnames = srcinfo.pkgnames();
+        if keep_debug {
+            pkgnames.push(Self::debug_package_name(&srcinfo.pkgbase));
+        }
+        let mut built = Vec::new();
+
         for entry in entries {
             let entry = entry.map_err(|e| build_makepkg_error(
                 format!("Error reading directory entry: {}", e),
                 "package discovery"
             ))?;
             let path = entry.path();
-            if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-                if (file_name.ends_with(".pkg.tar.zst") || file_name.ends_with(".pkg.tar.xz"))
-                    && !file_name.contains("-debug-")
-                    && file_name.starts_with(package_name)
-                {
-                    return Ok(path);
-                }
+            if let Some(file_name) = path.file_name().and_then(|f| f.to_str())
+                && let Some((name, _, _)) = Self::parse_package_filename(file_name)
+                && pkgnames.contains(&name)
+            {
+                built.push(path);
             }
         }
 
-        Err(build_makepkg_error(
-            format!("No valid package file found for {}", package_name),
-            "package discovery"
-        ))
+        if built.is_empty() {
+            return Err(build_makepkg_error(
+                format!("No valid package file found for {}", srcinfo.pkgbase),
+                "package discovery"
+            ));
+        }
+
+        Ok(built)
+    }
+
+    /// Name of the debug package makepkg produces for a pkgbase
+    pub fn debug_package_name(pkgbase: &str) -> String {
+        format!("{}-debug", pkgbase)
+    }
+
+    /// Splits a package file name (`name-pkgver-pkgrel-arch.pkg.tar.*`) into its
+    /// name, version (`pkgver-pkgrel`) and architecture.
+    pub fn parse_package_filename(file_name: &str) -> Option<(String, String, String)> {
+        let stem = file_name
+            .strip_suffix(".pkg.tar.zst")
+            .or_else(|| file_name.strip_suffix(".pkg.tar.xz"))?;
+
+        let mut parts = stem.rsplitn(4, '-');
+        let arch = parts.next()?;
+        let pkgrel = parts.next()?;
+        let pkgver = parts.next()?;
+        let name = parts.next()?;
+
+        Some((name.to_string(), format!("{}-{}", pkgver, pkgrel), arch.to_string()))
     }
 }
@@ -1,179 +1,356 @@
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use anyhow::Context;
 use colored::Colorize;
 use log::info;
+use serde::Serialize;
+use std::io::{self, IsTerminal, Write};
 use std::fs;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 use versions::Version;
-use chrono::{Utc, TimeZone};
 
-use crate::alpm::AlpmWrapper;
-use crate::aur::AurClient;
+use crate::alpm::{AlpmWrapper, RemoveOptions};
+use crate::aur::{AurBackend, AurClient, AurPackage, META_ARCHIVE_NAME, SearchField};
 use crate::build::PackageBuilder;
-use crate::config::AppConfig;
+use crate::config::{AppConfig, ConfigLayers, ConfigOrigin, DEFAULT_CONFIG_CONTENT};
+use crate::config_file;
+use crate::exec::{CommandRunner, Invocation};
+use crate::local_aur::SnapshotAur;
+use crate::sources::PackageSource;
+use crate::srcinfo::SrcInfo;
+use crate::tree::{self, LinkKind};
 use crate::error::{AlpmError, AurError, BuildError};
+use crate::output::{self, OutdatedPackage, OutputFormat, PackageInfo, RepoPackageInfo};
+use crate::search::{AUR_SOURCE, FoundPackage, SearchFilter, SearchResult, SortKey, parse_selection};
+use crate::progress;
+
+/// Flags shared by every subcommand
+#[derive(Args, Debug, Default)]
+pub struct GlobalOpts {
+    /// Output format for search, info, list, outdated and the build and removal
+    /// summaries. Progress goes to stderr for every format but human.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub format: OutputFormat,
+    /// Resolve and print every git clone, makepkg and pacman call without running them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Answer AUR queries from the snapshot saved by `sync-meta` instead of the RPC
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Ignore cached AUR responses and fetch everything again
+    #[arg(long, global = true)]
+    pub refresh: bool,
+    /// Override a config setting for this run, such as build.jobs=8
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+    /// Directory for built packages and cached AUR responses
+    #[arg(long, global = true)]
+    pub cache_dir: Option<PathBuf>,
+    /// Keep PKGBUILD clones in this directory and update them on later builds
+    #[arg(long, global = true)]
+    pub clone_dir: Option<PathBuf>,
+    /// Answer pacman and makepkg prompts with their defaults
+    #[arg(long, global = true)]
+    pub noconfirm: bool,
+}
+
+impl GlobalOpts {
+    /// Config settings given on the command line, which win over every other layer
+    pub fn config_overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        if let Some(dir) = &self.cache_dir {
+            overrides.push(("cache.dir".to_string(), dir.display().to_string()));
+        }
+        if let Some(dir) = &self.clone_dir {
+            overrides.push(("build.clone_dir".to_string(), dir.display().to_string()));
+        }
+        if self.noconfirm {
+            overrides.push(("behavior.confirm".to_string(), "false".to_string()));
+        }
+        overrides
+    }
+}
+
+fn parse_override(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {}", arg))
+}
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Search { query: String },
-    Install { package: String },
+    Search {
+        /// Search terms, all of which must match
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Field the AUR matches the search against
+        #[arg(long, value_enum, default_value_t)]
+        by: SearchField,
+        /// Sort results instead of keeping the AUR's order
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+        /// Show at most this many results
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Hide packages flagged out of date
+        #[arg(long)]
+        hide_out_of_date: bool,
+        /// Hide packages without a maintainer
+        #[arg(long)]
+        hide_orphans: bool,
+    },
+    Install {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Treat the targets as local PKGBUILD directories
+        #[arg(long)]
+        local: bool,
+    },
+    /// Clone or update the PKGBUILD repos of packages for inspection
+    Get {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Directory to clone into, defaults to the current directory
+        #[arg(long, short)]
+        dir: Option<PathBuf>,
+        /// Also fetch AUR dependencies
+        #[arg(long, short)]
+        recursive: bool,
+    },
+    /// Print a file from a package's AUR repo without cloning it
+    Show {
+        package: String,
+        /// File to print
+        #[arg(long, short, default_value = "PKGBUILD")]
+        file: String,
+        /// List the files in the repo instead
+        #[arg(long, short)]
+        list: bool,
+    },
+    /// Print the recursive dependency tree of a package
+    Tree {
+        package: String,
+        /// Show the installed packages that depend on an installed package instead
+        #[arg(long)]
+        reverse: bool,
+        /// Stop after this many levels
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Print a Graphviz DOT graph
+        #[arg(long)]
+        dot: bool,
+    },
+    /// Explain why a package is installed
+    Why {
+        package: String,
+    },
+    /// Build and cache local PKGBUILD directories without installing them
+    Build {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
     Info {
         package: String,
         #[arg(long)]
         deps: bool,
     },
-    Remove { package: String },
+    Remove {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Also remove packages that depend on the targets
+        #[arg(long)]
+        cascade: bool,
+        /// Don't keep .pacsave backups of modified config files
+        #[arg(long)]
+        nosave: bool,
+    },
     List,
-    Update { package: String },
+    /// Check cached packages against the AUR without updating them
+    Outdated {
+        /// Packages to check, defaults to every cached package
+        packages: Vec<String>,
+    },
+    Update {
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Download the AUR metadata snapshot used by --offline
+    SyncMeta,
+    /// Inspect, check or change the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print every effective setting and the layer it comes from
+    Show,
+    /// Print the effective value of a setting, such as build.jobs
+    Get { key: String },
+    /// Set a value in the user config, keeping its comments
+    Set {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+        /// Write the system config instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// Open the user config in the editor, then validate it
+    Edit {
+        /// Edit the system config instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// Print the path of the user config
+    Path {
+        /// Print the path of the system config instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// Check a config file for unknown keys and bad values, by default the
+    /// system and user configs
+    Validate {
+        path: Option<PathBuf>,
+        /// Check the file as a system config, where `locked` is allowed
+        #[arg(long)]
+        system: bool,
+    },
 }
 
 pub async fn handle_command(
     command: Commands,
+    opts: &GlobalOpts,
     config: &AppConfig,
-    aur: &AurClient,
+    aur: &dyn AurBackend,
     alpm: &AlpmWrapper,
 ) -> anyhow::Result<()> {
     match command {
-        Commands::Search { query } => {
-            info!("\n{}: {}", "Searching for".bold(), query.bright_green());
-            let results = aur.search_packages(&query).await?;
-            for pkg in results {
-                println!("\n{}: {}", "Name".bold(), pkg.name.bright_green());
-                println!("{}: {}", "Version".bold(), pkg.version.bright_cyan());
-                if let Some(desc) = pkg.description {
-                    println!("{}: {}", "Description".bold(), desc);
-                }
-                if let Some(url) = pkg.url {
-                    println!("{}: {}", "URL".bold(), url);
-                }
-                if let Some(maintainer) = pkg.maintainer {
-                    println!("{}: {}", "Maintainer".bold(), maintainer);
-                }
-            }
+        Commands::Search { query, by, sort, limit, hide_out_of_date, hide_orphans } => {
+            info!("\n{}: {}", "Searching for".bold(), query.join(" ").bright_green());
+            let filter = SearchFilter {
+                terms: query,
+                by,
+                sort,
+                limit,
+                hide_out_of_date,
+                hide_orphans,
+            };
+            let results = search_everywhere(&filter, aur, alpm).await?;
+            output::print_search_results(&results, opts.format)?;
         }
-        Commands::Install { package } => {
-            println!(
-                "{} {}",
-                "Attempting to install package:".bold(),
-                package.bright_green()
-            );
+        Commands::Install { packages, local: false } => {
+            install_targets(&packages, opts.format, config, aur, alpm).await?;
+        }
+        Commands::Install { packages, local: true } => {
+            let paths: Vec<PathBuf> = packages.iter().map(PathBuf::from).collect();
+            let summary = PackageBuilder::build_local_packages(&paths, aur, alpm, config).await
+                .context(format!("Failed to build local packages {:?}", packages))?;
+            output::print_build_summary(&summary, opts.format)?;
+            alpm.install_packages(&summary.target_paths())
+                .context(format!("\nFailed to install local packages {:?}", packages))?;
+        }
+        Commands::Get { packages, dir, recursive } => {
+            let dir = match dir {
+                Some(dir) => dir,
+                None => std::env::current_dir()?,
+            };
+            get_pkgbuilds(&packages, &dir, recursive, config, aur, alpm).await?;
+        }
+        Commands::Show { package, file, list } => {
+            let pkgbase = match aur.get_package_info(&package).await {
+                Ok(info) => info.package_base.unwrap_or(package),
+                Err(AurError::NotFound(_)) => package,
+                Err(e) => return Err(anyhow::anyhow!(e).context(format!("Failed to get AUR package info for {}", package))),
+            };
 
-            // First, check if the package is already installed
-            match alpm.is_package_installed(&package) {
-                Ok(true) => {
-                    println!(
-                        "{} {} {}",
-                        "Package".bold(),
-                        package.bright_green(),
-                        "is already installed"
-                    );
-                    return Ok(());
-                }
-                Ok(false) => {
-                    println!(
-                        "{} {} {}",
-                        "Package".bold(),
-                        package.bright_green(),
-                        "is not installed, proceeding with installation".bold()
-                    );
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(e as AlpmError).context("Failed to check if package is installed"));
+            // cgit serves single files, listing them needs the repo itself
+            if !list {
+                match aur.get_repo_file(&pkgbase, &file).await {
+                    Ok(content) => {
+                        page(&content, config, alpm.runner())?;
+                        return Ok(());
+                    }
+                    Err(e) => info!("cgit fetch failed, falling back to git: {}", e),
                 }
             }
 
-            let cache_dir = config.cache_path()?;
-
-            let package_paths_to_install = if let Some(cached_pkg) = PackageBuilder::find_cached_package(&cache_dir, &package) {
-                println!(
-                    "{} {} {}",
-                    "Using cached package:".bold(),
-                    package.bright_green(),
-                    format!("({:?})", cached_pkg).bright_cyan()
-                );
-                // If package is cached, collect all cached packages (main + deps)
-                let mut pkgs = vec![];
-                let deps = PackageBuilder::read_dependency_list(&package, &cache_dir).unwrap_or_default();
-                for dep in deps {
-                    if let Some(dep_pkg) = PackageBuilder::find_cached_package(&cache_dir, &dep) {
-                        pkgs.push(dep_pkg);
-                    }
+            let url = PackageSource::aur(&config.aur_base_url).git_url(&pkgbase);
+            let files = PackageBuilder::fetch_repo_files(&pkgbase, &url)
+                .context(format!("Failed to fetch the repo of {}", pkgbase))?;
+            if list {
+                for (path, _) in &files {
+                    println!("{}", path);
                 }
-                pkgs.push(cached_pkg);
-                pkgs
             } else {
-                println!(
-                    "{} {}",
-                    "Fetching package info for:".bold(),
-                    package.bright_green()
-                );
-
-                // Proceed with building if no cached package exists
-                let build_dir = config.temp_path().join(&package);
-                PackageBuilder::clone_repo(&package, &build_dir)
-                    .context(format!("Failed to clone repository for {}", package))?;
-
-                PackageBuilder::build_package_with_deps(
-                    &package,
-                    &build_dir,
-                    aur,
-                    &config,
-                ).await
-                .context(format!("Failed to build package {} with dependencies", package))?
-            };
-
-            // Install only the main package (last in the list)
-            if let Some(main_pkg) = package_paths_to_install.last() {
-                alpm.install_packages(&[main_pkg.clone()])
-                    .context(format!("\nFailed to install main package for {}", package))?;
+                let (_, content) = files.iter()
+                    .find(|(path, _)| *path == file)
+                    .ok_or_else(|| anyhow::anyhow!("{} not found in the repo of {}", file, pkgbase))?;
+                page(&String::from_utf8_lossy(content), config, alpm.runner())?;
+            }
+        }
+        Commands::Tree { package, reverse, depth, dot } => {
+            if reverse && !alpm.is_package_installed(&package)? {
+                anyhow::bail!("{} is not installed", package);
+            }
+            let resolved = tree::resolve(&package, reverse, depth, aur, alpm, config).await
+                .context(format!("Failed to resolve the dependencies of {}", package))?;
+            let root = tree::build_tree(&package, &resolved);
+            if dot {
+                print!("{}", tree::to_dot(&root));
+            } else {
+                output::print_dep_tree(&root, opts.format)?;
+            }
+        }
+        Commands::Why { package } => {
+            if !alpm.is_package_installed(&package)? {
+                anyhow::bail!("{} is not installed", package);
             }
+            let cache_dir = config.cache_path()?;
+            let chains = tree::why_chains(
+                &package,
+                |name| installed_dependents(name, &cache_dir, alpm),
+                |name| alpm.is_explicitly_installed(name).unwrap_or(false),
+            );
+            output::print_why(&package, &chains, opts.format)?;
+        }
+        Commands::Build { paths } => {
+            let summary = PackageBuilder::build_local_packages(&paths, aur, alpm, config).await
+                .context(format!("Failed to build local packages {:?}", paths))?;
+            output::print_build_summary(&summary, opts.format)?;
         }
         Commands::Info { package, deps } => {
+            let installed_version = alpm.installed_version(&package)?;
+
+            // Repo packages take precedence, like they do for pacman
+            if let Some((repo, repo_pkg)) = alpm.repo_package(&package)? {
+                let info = RepoPackageInfo { source: &repo, installed_version, package: &repo_pkg };
+                output::print_repo_package_info(&info, opts.format)?;
+                return Ok(());
+            }
+
             let pkg_info = aur.get_package_info(&package).await
                 .map_err(|e: AurError| {
                     eprintln!("\n{} {}", "✗ Failed to fetch AUR info:".red().bold(), e);
                     anyhow::anyhow!(e).context(format!("Failed to get AUR package info for {}", package))
                 })?;
 
-            println!("{}: {}", "\nPackage".bold(), pkg_info.name.green());
-            println!("{}: {}", "Version".bold(), pkg_info.version.bright_cyan());
-            if let Some(desc) = pkg_info.description {
-                println!("{}: {}", "Description".bold(), desc);
-            }
-            if let Some(url) = pkg_info.url {
-                println!("{}: {}", "URL".bold(), url);
-            }
-            if let Some(maintainer) = pkg_info.maintainer {
-                println!("{}: {}", "Maintainer".bold(), maintainer);
-            }
-            println!("{}: {}", "Votes".bold(), pkg_info.num_votes);
-            println!("{}: {}", "Popularity".bold(), pkg_info.popularity);
-            let first_submitted_dt = Utc.timestamp_opt(pkg_info.first_submitted as i64, 0).unwrap();
-            let last_modified_dt = Utc.timestamp_opt(pkg_info.last_modified as i64, 0).unwrap();
-            println!("{}: {}", "First Submitted".bold(), first_submitted_dt.format("%m/%d/%Y"));
-            println!("{}: {}\n", "Last Modified".bold(), last_modified_dt.format("%m/%d/%Y"));
-
+            let mut dependencies = None;
             if deps {
                 let temp_dir = tempdir()
                      .map_err(|e| {
                         eprintln!("\n{} {}", "✗ Failed to create temporary directory:".red().bold(), e);
                         anyhow::anyhow!(e).context("Failed to create temporary directory")
                      })?;
-                let build_dir = temp_dir.path().join(&package);
+                let pkgbase = pkg_info.package_base.as_deref().unwrap_or(&package);
+                let build_dir = temp_dir.path().join(pkgbase);
 
-                match PackageBuilder::clone_repo(&package, &build_dir) {
+                match PackageBuilder::clone_repo(pkgbase, &build_dir, aur, config) {
                     Ok(_) => {
                          match PackageBuilder::get_dependencies_from_srcinfo(&build_dir) {
-                             Ok(dependencies) => {
-                                 if !dependencies.is_empty() {
-                                     println!("{}:", "Dependencies".bold());
-                                     for dep in dependencies {
-                                         println!("  - {}", dep.bright_green());
-                                     }
-                                 } else {
-                                      println!("{}: {}", "Dependencies".bold(), "None found".bright_green());
-                                 }
-                             }
+                             Ok(found) => dependencies = Some(found),
                              Err(e) => {
                                  eprintln!("{} {}", "✗ Failed to extract dependencies:".red().bold(), 
         anyhow::anyhow!(e as BuildError).context("Error details"));
@@ -186,206 +363,792 @@ pub async fn handle_command(
                     }
                 }
             }
+
+            let info = PackageInfo {
+                source: AUR_SOURCE,
+                installed_version,
+                package: &pkg_info,
+                dependencies,
+            };
+            output::print_package_info(&info, opts.format)?;
         }
-        Commands::Remove { package } => {
-            match alpm.is_package_installed(&package) {
-                Ok(true) => {
-                    println!(
-                        "{} {} {}",
-                        "Package".bold(),
-                        package.bright_green(),
-                        "is installed, proceeding with removal".bold()
-                    );
+        Commands::Remove { packages, cascade, nosave } => {
+            let cache_dir = config.cache_path()?;
+            let mut targets: Vec<String> = Vec::new();
 
-                    let cache_dir = config.cache_path()?;
-                    let aur_deps_to_remove = PackageBuilder::read_dependency_list(&package, &cache_dir)
-                         .context("Failed to read AUR dependency list")?;
+            for package in dedup_targets(&packages) {
+                match alpm.is_package_installed(&package) {
+                    Ok(true) => {
+                        progress!(
+                            "{} {} {}",
+                            "Package".bold(),
+                            package.bright_green(),
+                            "is installed, proceeding with removal".bold()
+                        );
+                        targets.push(package);
+                    }
+                    Err(AlpmError::NotFound(_)) | Ok(false) => {
+                        eprintln!("{} {}", "✗ Package not found in system:".red().bold(), package.bright_red());
+                    }
+                    Err(e) => {
+                        return Err(anyhow::anyhow!(e as AlpmError).context("Failed to check if package is installed"));
+                    }
+                }
+            }
 
-                    let mut packages_to_remove = vec![package.clone()];
-                    packages_to_remove.extend(aur_deps_to_remove.clone());
+            if targets.is_empty() {
+                return Ok(());
+            }
 
-                    alpm.remove_package(&packages_to_remove)
-                        .context(format!("Failed to remove packages {:?}", packages_to_remove))?;
+            // pacman -Rc takes every package depending on the targets along
+            if cascade {
+                targets = cascade_targets(&targets, |package| alpm.required_by(package).unwrap_or_default());
+            }
 
-                    for dep in &aur_deps_to_remove {
-                        PackageBuilder::delete_cached_package(&cache_dir, dep)?;
+            // pacman only knows runtime dependencies, lilac also records build-time ones
+            for package in &targets {
+                for (dependent, _) in installed_dependents(package, &cache_dir, alpm) {
+                    if !targets.contains(&dependent) {
+                        eprintln!(
+                            "{} {} {} {}",
+                            "⚠ Warning:".yellow().bold(),
+                            package.bright_green(),
+                            "is needed by".bold(),
+                            dependent.bright_yellow()
+                        );
                     }
-
-                    PackageBuilder::delete_cached_package(&cache_dir, &package)
-                        .context("Failed to delete cached package")?;
-                }
-                Err(AlpmError::NotFound(_)) => {
-                    eprintln!("{} {}", "✗ Package not found in system:".red().bold(), package.bright_red());
                 }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(e as AlpmError).context("Failed to check if package is installed"));
-                }
-                Ok(false) => { // WHEN this ever happens I'm in some deep, deep shit
-                    eprintln!("{} {}", "✗ Package not found in system:".red().bold(), package.bright_red());
+            }
+
+            // A debug package goes once no installed package of its pkgbase is left
+            let mut debug_targets: Vec<String> = Vec::new();
+            for package in &targets {
+                let pkgbase = PackageBuilder::cached_pkgbase(&cache_dir, package);
+                let debug = PackageBuilder::debug_package_name(&pkgbase);
+                let pkgbase_removed = PackageBuilder::cached_pkgbase_members(&cache_dir, &pkgbase).iter()
+                    .all(|member| targets.contains(member) || !alpm.is_package_installed(member).unwrap_or(false));
+                if pkgbase_removed
+                    && !targets.contains(&debug)
+                    && !debug_targets.contains(&debug)
+                    && alpm.is_package_installed(&debug).unwrap_or(false)
+                {
+                    debug_targets.push(debug);
                 }
             }
+            targets.extend(debug_targets);
+
+            let plan = plan_removal(
+                &targets,
+                |package| PackageBuilder::read_dependency_list(package, &cache_dir).unwrap_or_default(),
+                |package| installed_dependents(package, &cache_dir, alpm).into_iter().map(|(name, _)| name).collect(),
+                |package| alpm.is_package_installed(package).unwrap_or(false),
+                |package| alpm.is_explicitly_installed(package).unwrap_or(false),
+            );
+            let options = RemoveOptions { cascade, nosave };
+            output::print_removal_plan(&plan, opts.format)?;
+
+            alpm.remove_package(&plan.remove, options)
+                .context(format!("Failed to remove packages {:?}", plan.remove))?;
+
+            // Dependencies that are kept stay in the cache for the packages still using them
+            if opts.dry_run {
+                return Ok(());
+            }
+            for package in &plan.remove {
+                PackageBuilder::delete_cached_package(&cache_dir, package)
+                    .context("Failed to delete cached package")?;
+            }
         }
         Commands::List => {
             let cache_dir = config.cache_path()?;
-            let entries = fs::read_dir(&cache_dir)
+            let packages = PackageBuilder::list_cached_packages(&cache_dir)
                 .context("Failed to read cache directory")?;
+            output::print_cached_packages(&packages, opts.format)?;
+        }
+        Commands::Outdated { packages } => {
+            let cache_dir = config.cache_path()?;
+            let cached: Vec<(String, String)> = if packages.is_empty() {
+                PackageBuilder::list_cached_packages(&cache_dir)
+                    .context("Failed to read cache directory")?
+                    .into_iter()
+                    .filter(|pkg| !config.is_ignored(&pkg.name))
+                    .map(|pkg| (pkg.name, pkg.version))
+                    .collect()
+            } else {
+                dedup_targets(&packages).into_iter()
+                    .filter_map(|name| {
+                        let version = PackageBuilder::cached_version(&cache_dir, &name);
+                        if version.is_none() {
+                            eprintln!("{} {}", "✗ Package not found in cache:".red().bold(), name.bright_red());
+                        }
+                        version.map(|version| (name, version))
+                    })
+                    .collect()
+            };
 
-            let mut packages_info = Vec::new();
-            for entry in entries {
-                let entry = entry.context("Failed to read cache directory entry")?;
-                let path = entry.path();
-                if let Some(file_name) = path.file_name().and_then(|f| f.to_str()) {
-                    if file_name.ends_with(".pkg.tar.zst") || file_name.ends_with(".pkg.tar.xz") {
-                        // Remove the file extension
-                        let file_name_without_ext = file_name
-                            .strip_suffix(".pkg.tar.zst")
-                            .or_else(|| file_name.strip_suffix(".pkg.tar.xz"))
-                            .unwrap_or(file_name);
-
-                        let package_name;
-                        let version;
-
-                        // Find the index of the first hyphen followed by a digit (marks start of version)
-                        let version_start_index = file_name_without_ext.char_indices()
-                            .find(|(i, c)| {
-                                *c == '-' && file_name_without_ext.chars().nth(i + 1).map_or(false, |next_c| next_c.is_digit(10))
-                            })
-                            .map(|(i, _)| i);
-
-                        if let Some(index) = version_start_index {
-                            let name_part = &file_name_without_ext[..index];
-                            let version_with_arch = &file_name_without_ext[index + 1..];
-
-                            // Remove the architecture from the version string
-                            let arch_start_index = version_with_arch.rfind('-');
-                            let version_only = if let Some(arch_idx) = arch_start_index {
-                                version_with_arch[..arch_idx].to_string()
-                            } else {
-                                version_with_arch.to_string()
-                            };
-                            package_name = name_part.to_string();
-                            version = version_only;
-                        } else {
-                             package_name = file_name_without_ext.to_string();
-                             version = "unknown".to_string();
-                        };
-                        packages_info.push(format!("{} ({})", package_name, version));
-                    }
-                }
-            }
-
-            packages_info.sort();
+            let names: Vec<String> = cached.iter().map(|(name, _)| name.clone()).collect();
+            let latest_pkgs = aur.get_packages_info(&names).await
+                .context("Failed to fetch latest package info from AUR")?;
 
-            if packages_info.is_empty() {
-                println!("\n{}\n", "No packages installed via lilac found in cache.".bold());
-            } else {
-                println!("\n{}\n", "Packages installed via lilac:".bold());
-                for pkg_info in packages_info {
-                    println!("  - {}\n", pkg_info.bright_green());
-                }
-            }
+            output::print_outdated(&find_outdated(&cached, &latest_pkgs), opts.format)?;
         }
-        Commands::Update { package } => {
-            println!(
+        Commands::Update { packages } => {
+            let cache_dir = config.cache_path()?;
+            let packages: Vec<String> = dedup_targets(&packages).into_iter()
+                .filter(|package| {
+                    let ignored = config.is_ignored(package);
+                    if ignored {
+                        progress!("{} {} {}", "Skipping".bold(), package.bright_yellow(), "(in behavior.ignore)".bold());
+                    }
+                    !ignored
+                })
+                // A pinned ref would build the same version again, however new the AUR's is
+                .filter(|package| {
+                    let pkgbase = PackageBuilder::cached_pkgbase(&cache_dir, package);
+                    let pinned = config.package_override(&pkgbase).and_then(|package| package.git_ref.as_deref());
+                    if let Some(git_ref) = pinned {
+                        progress!(
+                            "{} {} {}",
+                            "Skipping".bold(),
+                            package.bright_yellow(),
+                            format!("(pinned to {} in [package.{}])", git_ref, pkgbase).bold()
+                        );
+                    }
+                    pinned.is_none()
+                })
+                .collect();
+            if packages.is_empty() {
+                return Ok(());
+            }
+            progress!(
                 "\n{} {}",
-                "Checking for updates for package:".bold(),
-                package.bright_green()
+                "Checking for updates for packages:".bold(),
+                packages.join(" ").bright_green()
             );
 
-            let latest_pkg = aur.get_package_info(&package).await
+            let latest_pkgs = aur.get_packages_info(&packages).await
                 .context("Failed to fetch latest package info from AUR")?;
 
-            match alpm.is_package_installed(&package) {
-                Ok(true) => {
-                    println!(
+            let mut outdated: Vec<String> = Vec::new();
+
+            for package in &packages {
+                let Some(latest_pkg) = latest_pkgs.iter().find(|p| p.name == *package) else {
+                    eprintln!("\n{} {}\n", "✗ Package not found in AUR:".red().bold(), package.bright_red());
+                    continue;
+                };
+
+                match alpm.is_package_installed(package) {
+                    Ok(true) => {
+                        progress!(
+                            "{} {} {}",
+                            "Package".bold(),
+                            package.bright_green(),
+                            "is installed, checking for updates...".bold()
+                        );
+                    }
+                    Err(AlpmError::NotFound(_)) => {
+                        eprintln!("\n{} {}\n", "✗ Package not found in system:".red().bold(), package.bright_red());
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                    Ok(false) => {
+                        eprintln!("\n{} {}\n", "✗ Package not installed:".red().bold(), package.bright_red());
+                        continue;
+                    }
+                }
+
+                let cached_version = PackageBuilder::cached_version(&cache_dir, package)
+                    .unwrap_or_else(|| "unknown".to_string());
+
+                progress!(
+                    "{}: {} (cached) vs {} (latest)",
+                    "Version comparison".bold(),
+                    cached_version.bright_cyan(),
+                    latest_pkg.version.bright_green()
+                );
+
+                let cached_ver = Version::new(&cached_version);
+                let latest_ver = Version::new(&latest_pkg.version);
+
+                if cached_ver < latest_ver {
+                    progress!(
                         "{} {} {}",
+                        "Updating package:".bold(),
+                        package.bright_green(),
+                        format!("(from {} to {})", cached_version, latest_pkg.version).bright_cyan()
+                    );
+                    outdated.push(package.clone());
+                } else {
+                    progress!(
+                        "\n{} {} {}",
                         "Package".bold(),
                         package.bright_green(),
-                        "is installed, checking for updates...".bold()
+                        "is already up to date.\n".bold()
                     );
                 }
-                Err(AlpmError::NotFound(_)) => {
-                    eprintln!("\n{} {}\n", "✗ Package not found in system:".red().bold(), package.bright_red());
-                    return Ok(());
-                }
-                Err(e) => return Err(e.into()),
-                Ok(false) => {
-                    eprintln!("\n{} {}\n", "✗ Package not installed:".red().bold(), package.bright_red());
-                    return Ok(());
-                }
             }
 
-            fn extract_version_from_filename(file_name: &str, package_name: &str) -> Option<String> {
-                let stripped = file_name.strip_prefix(package_name)?;
-                let parts: Vec<&str> = stripped.split('-').collect();
-                if parts.len() >= 3 {
-                    // Combine version and release (e.g., "0.7.7-1")
-                    Some(format!("{}-{}", parts[1], parts[2]))
-                } else {
-                    None
-                }
+            if outdated.is_empty() {
+                return Ok(());
             }
 
-            let cache_dir = config.cache_path()?;
-            let cached_pkg = PackageBuilder::find_cached_package(&cache_dir, &package);
-            let cached_version = match cached_pkg {
-                Some(path) => {
-                    let file_name = path.file_name().unwrap().to_str().unwrap();
-                    extract_version_from_filename(file_name, &package).unwrap_or_else(|| {
-                        println!("{}", "✗ Failed to extract version from cached filename.".red().bold());
-                        "unknown".to_string()
-                    })
-                }
-                None => "unknown".to_string(),
-            };
+            // Building replaces the stale cached artifacts
+            let summary = PackageBuilder::build_packages_with_deps(
+                &outdated,
+                &outdated,
+                aur,
+                alpm,
+                config,
+            ).await
+            .context("Failed to rebuild packages")?;
+            output::print_build_summary(&summary, opts.format)?;
 
+            alpm.install_packages(&summary.target_paths())
+                .context("Failed to install updated package(s)")?;
+
+            progress!("\n{}", "✓ Update completed successfully!".green().bold());
+        }
+        Commands::Config { action } => handle_config(action, opts, alpm.runner())?,
+        Commands::SyncMeta => {
+            let path = config.meta_archive_path()?;
+            let client = AurClient::with_options(config.aur_base_url.clone(), config.client_options())?;
+            let count = sync_meta(&client, &path).await?;
             println!(
-                "{}: {} (cached) vs {} (latest)",
-                "Version comparison".bold(),
-                cached_version.bright_cyan(),
-                latest_pkg.version.bright_green()
+                "\n{} {} {}",
+                "✓ Saved metadata of".green().bold(),
+                count.to_string().bright_green(),
+                format!("AUR packages to {:?}", path).green().bold()
             );
+        }
+    }
+
+    Ok(())
+}
+
+/// The config file a `lilac config` action works on, and the layer it is
+fn config_file_path(system: bool) -> anyhow::Result<(PathBuf, ConfigOrigin)> {
+    if system {
+        return Ok((AppConfig::system_config_path(), ConfigOrigin::SystemFile));
+    }
+    let path = AppConfig::user_config_path().context("Could not determine the config directory")?;
+    Ok((path, ConfigOrigin::UserFile))
+}
+
+fn read_config_layers(opts: &GlobalOpts) -> anyhow::Result<ConfigLayers> {
+    Ok(ConfigLayers::read(
+        Some(&AppConfig::system_config_path()),
+        AppConfig::user_config_path().as_deref(),
+        &opts.config_overrides(),
+    )?)
+}
+
+/// Validates a config file read as the `origin` layer, printing every issue.
+/// Fails when there is any.
+fn validate_config_file(path: &Path, origin: ConfigOrigin) -> anyhow::Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let issues = config_file::validate(&content, origin);
+    if issues.is_empty() {
+        println!("{} {}", "✓".green().bold(), format!("{} is valid", path.display()).bold());
+        return Ok(());
+    }
+    for issue in &issues {
+        eprintln!("{}:{}: {}", path.display(), issue.line, issue.message.bright_red());
+    }
+    anyhow::bail!("{} has {} problem(s)", path.display(), issues.len())
+}
+
+/// `lilac config`, which runs before the config is loaded so a broken config
+/// can still be checked and fixed
+pub fn handle_config(action: ConfigAction, opts: &GlobalOpts, runner: &dyn CommandRunner) -> anyhow::Result<()> {
+    match action {
+        ConfigAction::Show | ConfigAction::Get { .. } => {
+            let layers = read_config_layers(opts)?;
+            let config = layers.merge()?;
+            if let ConfigAction::Get { key } = action {
+                let value = config_file::effective_value(&config, &key)?
+                    .with_context(|| format!("{} is not set", key))?;
+                println!("{}", output::setting_value(&value));
+            } else {
+                output::print_settings(&config_file::effective_settings(&config, &layers)?, opts.format)?;
+            }
+        }
+        ConfigAction::Set { key, value, system } => {
+            let (path, origin) = config_file_path(system)?;
+            if !system && read_config_layers(opts)?.is_locked(&key) {
+                anyhow::bail!("{} is locked by {}", key, AppConfig::system_config_path().display());
+            }
+            let content = if path.exists() {
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?
+            } else {
+                DEFAULT_CONFIG_CONTENT.to_string()
+            };
+            let edited = config_file::set_value(&content, &key, &value, origin)?;
+            if opts.dry_run {
+                println!("{} {} = {} in {}", "Would set".bold(), key.bright_green(), value, path.display());
+                return Ok(());
+            }
+            AppConfig::create_config_file(&path)?;
+            fs::write(&path, edited).with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("{} {} = {}", "✓ Set".green().bold(), key.bright_green(), value);
+        }
+        ConfigAction::Edit { system } => {
+            let (path, origin) = config_file_path(system)?;
+            if !opts.dry_run {
+                AppConfig::create_config_file(&path)?;
+            }
+            let editor = AppConfig::load_with(&opts.config_overrides())
+                .map(|config| config.editor())
+                .unwrap_or_else(|_| AppConfig::default_editor());
+            let mut words = editor.split_whitespace();
+            let program = words.next().context("No editor configured")?;
+            let invocation = Invocation::new(program).args(words).arg(path.display().to_string());
+            let output = runner.run(&invocation)?;
+            if !output.success {
+                anyhow::bail!("{} failed: {}", invocation, output);
+            }
+            if !opts.dry_run {
+                validate_config_file(&path, origin)?;
+            }
+        }
+        ConfigAction::Path { system } => println!("{}", config_file_path(system)?.0.display()),
+        ConfigAction::Validate { path: Some(path), system } => {
+            let origin = if system { ConfigOrigin::SystemFile } else { ConfigOrigin::UserFile };
+            validate_config_file(&path, origin)?;
+        }
+        ConfigAction::Validate { path: None, system } => {
+            let mut files = vec![config_file_path(true)?];
+            if !system {
+                files.push(config_file_path(false)?);
+            }
+            let mut failed = false;
+            for (path, origin) in files.into_iter().filter(|(path, _)| path.exists()) {
+                if let Err(e) = validate_config_file(&path, origin) {
+                    eprintln!("{}", e);
+                    failed = true;
+                }
+            }
+            if failed {
+                anyhow::bail!("The config has problems");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Downloads the metadata archive and replaces the snapshot at `path` once it
+/// has been read back successfully. Returns the number of packages in it.
+pub async fn sync_meta(aur: &AurClient, path: &Path) -> anyhow::Result<usize> {
+    println!("{} {}", "Downloading".bold(), META_ARCHIVE_NAME.bright_cyan());
+    let partial = path.with_extension("part");
+    aur.download_meta_archive(&partial).await
+        .context("Failed to download the AUR metadata archive")?;
+
+    let snapshot = match SnapshotAur::open(&partial) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(anyhow::Error::new(e).context("Downloaded metadata archive is unreadable"));
+        }
+    };
+    fs::rename(&partial, path).with_context(|| format!("Failed to move snapshot to {:?}", path))?;
+    Ok(snapshot.len())
+}
+
+/// Shows text through the configured pager when stdout is a terminal, and
+/// prints it otherwise or when the pager can't be started. Paging only displays
+/// the text, so dry-run mode prints it too.
+fn page(content: &str, config: &AppConfig, runner: &dyn CommandRunner) -> io::Result<()> {
+    let pager = config.pager();
+    let mut parts = pager.split_whitespace();
+    if io::stdout().is_terminal()
+        && !runner.is_dry_run()
+        && let Some(program) = parts.next()
+        && runner.run(&Invocation::new(program).args(parts).input(content.as_bytes())).is_ok()
+    {
+        return Ok(());
+    }
+    print!("{}", content);
+    Ok(())
+}
+
+/// Removes duplicate targets while keeping the order they were given in.
+pub fn dedup_targets(packages: &[String]) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    for package in packages {
+        if !targets.contains(package) {
+            targets.push(package.clone());
+        }
+    }
+    targets
+}
+
+/// What `lilac remove` removes, and the recorded dependencies it keeps
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RemovalPlan {
+    /// Targets first, then the recorded dependencies nothing else needs
+    pub remove: Vec<String>,
+    pub kept: Vec<KeptDependency>,
+}
+
+/// A recorded dependency left installed, with the reason
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeptDependency {
+    pub name: String,
+    /// Installed packages outside the removal that still need it
+    pub needed_by: Vec<String>,
+    /// Installed explicitly by the user at some point
+    pub explicit: bool,
+}
+
+/// The targets followed by every installed package that depends on them, directly
+/// or through another dependent, as `pacman -Rc` removes them
+pub fn cascade_targets(targets: &[String], required_by: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+    let mut cascaded = targets.to_vec();
+    let mut i = 0;
+    while i < cascaded.len() {
+        for dependent in required_by(&cascaded[i]) {
+            if !cascaded.contains(&dependent) {
+                cascaded.push(dependent);
+            }
+        }
+        i += 1;
+    }
+    cascaded
+}
+
+/// Works out which recorded dependencies of the targets can go with them. The
+/// recorded dependencies of removed dependencies are followed too. A dependency is
+/// kept when it was installed explicitly or when a package outside the removal still
+/// needs it, which may in turn keep the dependencies it needs.
+pub fn plan_removal(
+    targets: &[String],
+    recorded_deps: impl Fn(&str) -> Vec<String>,
+    dependents: impl Fn(&str) -> Vec<String>,
+    is_installed: impl Fn(&str) -> bool,
+    is_explicit: impl Fn(&str) -> bool,
+) -> RemovalPlan {
+    let mut candidates: Vec<String> = Vec::new();
+    let mut kept: Vec<KeptDependency> = Vec::new();
+    let mut i = 0;
+    let mut queue: Vec<String> = targets.to_vec();
+    while i < queue.len() {
+        for dep in recorded_deps(&queue[i]) {
+            if targets.contains(&dep) || candidates.contains(&dep) || kept.iter().any(|k| k.name == dep) {
+                continue;
+            }
+            if !is_installed(&dep) {
+                continue;
+            }
+            if is_explicit(&dep) {
+                kept.push(KeptDependency { name: dep, needed_by: Vec::new(), explicit: true });
+                continue;
+            }
+            candidates.push(dep.clone());
+            queue.push(dep);
+        }
+        i += 1;
+    }
 
-            let cached_ver = Version::new(&cached_version);
-            let latest_ver = Version::new(&latest_pkg.version);
+    // Keeping one dependency can make others needed again, so repeat until stable
+    loop {
+        let removing: Vec<&String> = targets.iter().chain(&candidates).collect();
+        let newly_kept: Vec<KeptDependency> = candidates.iter()
+            .filter_map(|dep| {
+                let needed_by: Vec<String> = dependents(dep).into_iter()
+                    .filter(|dependent| !removing.contains(&dependent))
+                    .collect();
+                (!needed_by.is_empty()).then(|| KeptDependency { name: dep.clone(), needed_by, explicit: false })
+            })
+            .collect();
+        if newly_kept.is_empty() {
+            break;
+        }
+        candidates.retain(|dep| !newly_kept.iter().any(|k| k.name == *dep));
+        kept.extend(newly_kept);
+    }
+
+    let mut remove = targets.to_vec();
+    remove.extend(candidates);
+    RemovalPlan { remove, kept }
+}
+
+/// Pairs cached `(name, version)` entries with their AUR info and keeps the ones
+/// whose AUR version is newer. Packages missing from the AUR are skipped.
+pub fn find_outdated(cached: &[(String, String)], latest_pkgs: &[AurPackage]) -> Vec<OutdatedPackage> {
+    cached.iter()
+        .filter_map(|(name, cached_version)| {
+            let latest = latest_pkgs.iter().find(|p| p.name == *name)?;
+            (Version::new(cached_version) < Version::new(&latest.version)).then(|| OutdatedPackage {
+                name: name.clone(),
+                cached_version: cached_version.clone(),
+                latest_version: latest.version.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Searches the sync databases and the AUR. Sync databases only know names and
+/// descriptions, so they are skipped for the other search fields. Repo results are
+/// listed before AUR ones, and every result is marked with its installed version.
+pub async fn search_everywhere(
+    filter: &SearchFilter,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+) -> anyhow::Result<Vec<SearchResult>> {
+    filter.check().map_err(anyhow::Error::msg)?;
+    let by = filter.by;
+    let mut results: Vec<SearchResult> = Vec::new();
+    if matches!(by, SearchField::Name | SearchField::NameDesc) {
+        let mut repo_results = alpm.search_repos(&filter.terms)?;
+        if by == SearchField::Name {
+            repo_results.retain(|(_, pkg)| filter.terms.iter().all(|term| pkg.name.contains(&term.to_lowercase())));
+        }
+        if filter.sort == Some(SortKey::Name) {
+            repo_results.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        }
+        results.extend(repo_results.into_iter().map(|(repo, pkg)| SearchResult {
+            source: repo,
+            installed_version: None,
+            package: FoundPackage::Repo(pkg),
+        }));
+    }
+
+    let rpc_term = filter.rpc_term().unwrap_or_default();
+    let aur_results = filter.apply(aur.search_packages_by(rpc_term, by).await?);
+    results.extend(aur_results.into_iter().map(|pkg| SearchResult {
+        source: AUR_SOURCE.to_string(),
+        installed_version: None,
+        package: FoundPackage::Aur(pkg),
+    }));
+
+    if let Some(limit) = filter.limit {
+        results.truncate(limit);
+    }
+    for result in &mut results {
+        result.installed_version = alpm.installed_version(result.package.name())?;
+    }
+
+    Ok(results)
+}
+
+/// Installs a set of targets. Targets found in a sync database are installed with
+/// pacman, the rest are built from the AUR and installed in a single transaction.
+///
+/// pacman -S only takes sync database names and -U only package files, so the two
+/// kinds of targets can't share a transaction. Both are installed once every build
+/// succeeded. Dependencies are installed earlier, since makepkg needs them to build.
+pub async fn install_targets(
+    packages: &[String],
+    format: OutputFormat,
+    config: &AppConfig,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+) -> anyhow::Result<()> {
+    progress!(
+        "{} {}",
+        "Attempting to install packages:".bold(),
+        packages.join(" ").bright_green()
+    );
 
-            if cached_ver < latest_ver {
-                println!(
+    // First, check which packages are already installed
+    let mut to_install: Vec<String> = Vec::new();
+    let mut repo_targets: Vec<String> = Vec::new();
+    for package in dedup_targets(packages) {
+        match alpm.is_package_installed(&package) {
+            Ok(true) => {
+                progress!(
+                    "{} {} is already installed",
+                    "Package".bold(),
+                    package.bright_green()
+                );
+            }
+            Ok(false) => {
+                progress!(
                     "{} {} {}",
-                    "Updating package:".bold(),
+                    "Package".bold(),
                     package.bright_green(),
-                    format!("(from {} to {})", cached_version, latest_pkg.version).bright_cyan()
+                    "is not installed, proceeding with installation".bold()
                 );
+                if alpm.is_package_available(&package)? {
+                    repo_targets.push(package);
+                } else {
+                    to_install.push(package);
+                }
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!(e as AlpmError).context("Failed to check if package is installed"));
+            }
+        }
+    }
 
-                let build_dir = config.temp_path().join(&package);
-                PackageBuilder::clone_repo(&package, &build_dir)
-                    .context("Failed to clone repository for update")?;
+    if to_install.is_empty() {
+        return install_repo_targets(&repo_targets, alpm);
+    }
 
-                let package_paths = PackageBuilder::build_package_with_deps(
-                    &package,
-                    &build_dir,
-                    aur,
-                    &config,
-                ).await
-                .context("Failed to rebuild package")?;
+    let summary = PackageBuilder::build_packages_with_deps(
+        &to_install,
+        &[],
+        aur,
+        alpm,
+        config,
+    ).await
+    .context(format!("Failed to build packages {:?} with dependencies", to_install))?;
+    output::print_build_summary(&summary, format)?;
 
-                alpm.remove_package(&[package])
-                    .context("Failed to remove old package")?;
+    // A failed build leaves none of the targets installed
+    install_repo_targets(&repo_targets, alpm)?;
+    alpm.install_packages(&summary.target_paths())
+        .context(format!("\nFailed to install packages {:?}", to_install))?;
 
-                alpm.install_packages(&package_paths)
-                    .context("Failed to install updated package(s)")?;
+    Ok(())
+}
 
-                println!("\n{}", "✓ Update completed successfully!".green().bold());
+fn install_repo_targets(repo_targets: &[String], alpm: &AlpmWrapper) -> anyhow::Result<()> {
+    if !repo_targets.is_empty() {
+        alpm.install_repo_packages(repo_targets)
+            .context(format!("Failed to install repo packages {:?}", repo_targets))?;
+    }
+    Ok(())
+}
+
+/// `lilac <terms>`: searches the repos and the AUR, lets the user pick results by
+/// number and installs the selection.
+pub async fn interactive_install(
+    terms: Vec<String>,
+    format: OutputFormat,
+    config: &AppConfig,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+) -> anyhow::Result<()> {
+    let filter = SearchFilter { terms, by: SearchField::NameDesc, ..Default::default() };
+    let results = search_everywhere(&filter, aur, alpm).await?;
+
+    if results.is_empty() {
+        println!("\n{}\n", "No packages found.".bold());
+        return Ok(());
+    }
+
+    output::print_numbered_results(&results);
+
+    print!("\n{} ", "==> Packages to install (eg: 1 2 3, 1-3 or ^4):".bold());
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    let selected = parse_selection(&input, results.len())
+        .map_err(|e| anyhow::anyhow!(e).context("Invalid selection"))?;
+    if selected.is_empty() {
+        println!("{}", "Nothing selected.".bold());
+        return Ok(());
+    }
+
+    let packages: Vec<String> = selected.into_iter()
+        .map(|index| results[index].package.name().to_string())
+        .collect();
+    install_targets(&packages, format, config, aur, alpm).await
+}
+
+/// `lilac get`: clones or updates the PKGBUILD repo of every package's pkgbase into
+/// `dir`, following AUR dependencies when `recursive` is set. A dry run only prints
+/// the clones and fetches.
+pub async fn get_pkgbuilds(
+    packages: &[String],
+    dir: &Path,
+    recursive: bool,
+    config: &AppConfig,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+) -> anyhow::Result<()> {
+    let runner = alpm.runner();
+    if !runner.is_dry_run() {
+        fs::create_dir_all(dir).context(format!("Failed to create {:?}", dir))?;
+    }
+
+    let mut pending = dedup_targets(packages);
+    let mut seen: Vec<String> = pending.clone();
+    let mut fetched_bases: Vec<String> = Vec::new();
+
+    while !pending.is_empty() {
+        let infos = aur.get_packages_info(&pending).await
+            .context(format!("Failed to get AUR package info for {:?}", pending))?;
+        let mut dependencies: Vec<String> = Vec::new();
+
+        for package in pending.drain(..) {
+            let pkgbase = match infos.iter().find(|info| info.name == package) {
+                Some(info) => info.package_base.clone().unwrap_or_else(|| package.clone()),
+                None if config.has_extra_sources() => package.clone(),
+                None => {
+                    eprintln!("{} {}", "✗ Package not found in the AUR:".red().bold(), package);
+                    continue;
+                }
+            };
+            if fetched_bases.contains(&pkgbase) {
+                continue;
+            }
+
+            let repo_path = dir.join(&pkgbase);
+            let mut planned_srcinfo: Option<String> = None;
+            if repo_path.join(".git").is_dir() && runner.is_dry_run() {
+                runner.announce(&format!("git pull {:?}", repo_path));
+            } else if repo_path.join(".git").is_dir() {
+                if PackageBuilder::update_repo(&pkgbase, &repo_path)? {
+                    println!("{} {}", "Updated:".bold(), pkgbase.bright_green());
+                } else {
+                    println!("{} {} is up to date", "Repository:".bold(), pkgbase.bright_green());
+                }
+            } else if repo_path.exists() {
+                println!("{} {} already exists and is not a git repo, skipping.", "Directory:".bold(), pkgbase.bright_green());
+            } else if runner.is_dry_run() {
+                let (_, srcinfo) = PackageBuilder::plan_clone(&pkgbase, &repo_path, aur, config, runner).await?;
+                planned_srcinfo = Some(srcinfo);
             } else {
-                println!(
-                    "\n{} {} {}",
-                    "Package".bold(),
-                    package.bright_green(),
-                    "is already up to date.\n".bold()
+                PackageBuilder::clone_repo(&pkgbase, &repo_path, aur, config)?;
+            }
+            println!("  {}", format!("{:?}", repo_path).bright_cyan());
+
+            // Generating a .SRCINFO would source the PKGBUILD before anyone read it
+            if recursive && let Some(srcinfo) = &planned_srcinfo {
+                dependencies.extend(SrcInfo::parse(srcinfo).all_dependencies());
+            } else if recursive && repo_path.join(".SRCINFO").is_file() {
+                dependencies.extend(SrcInfo::from_dir(&repo_path)?.all_dependencies());
+            } else if recursive {
+                eprintln!(
+                    "{} {}{}",
+                    "✗ No .SRCINFO in".red().bold(),
+                    pkgbase.bright_red(),
+                    ", not following its dependencies".bold()
                 );
             }
+            fetched_bases.push(pkgbase);
+        }
+
+        // Only dependencies that pacman can't install from a sync database are fetched
+        for dep in dependencies {
+            if !seen.contains(&dep) && !alpm.is_package_available(&dep)? {
+                seen.push(dep.clone());
+                pending.push(dep);
+            }
         }
     }
 
     Ok(())
 }
+
+/// Installed packages that depend on a package, according to the local database or
+/// to lilac's dependency records
+fn installed_dependents(package: &str, cache_dir: &Path, alpm: &AlpmWrapper) -> Vec<(String, LinkKind)> {
+    let mut dependents: Vec<(String, LinkKind)> = alpm.required_by(package)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| (name, LinkKind::RequiredBy))
+        .collect();
+    for name in PackageBuilder::recorded_dependents(cache_dir, package) {
+        if !dependents.iter().any(|(known, _)| *known == name)
+            && alpm.is_package_installed(&name).unwrap_or(false)
+        {
+            dependents.push((name, LinkKind::LilacRecord));
+        }
+    }
+    dependents
+}
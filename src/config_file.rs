@@ -0,0 +1,217 @@
+use crate::config::{AppConfig, ConfigLayers, ConfigOrigin, is_list_key};
+use config::ConfigError;
+use serde::Serialize;
+use std::fmt;
+use std::ops::Range;
+use toml::Spanned;
+use toml::de::DeTable;
+use toml_edit::{Array, Document, DocumentMut, Item, Table, TableLike, Value};
+
+/// A problem found in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// 1-based line the problem is on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// An effective setting as shown by `lilac config show`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Setting {
+    /// Dotted key, such as `build.jobs`
+    pub key: String,
+    pub value: toml::Value,
+    pub origin: ConfigOrigin,
+    /// Locked by the system config
+    pub locked: bool,
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content.get(..offset).map_or(1, |before| before.matches('\n').count() + 1)
+}
+
+fn path_segments(path: &serde_ignored::Path, segments: &mut Vec<String>) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            path_segments(parent, segments);
+            segments.push(index.to_string());
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            path_segments(parent, segments);
+            segments.push(key.clone());
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => path_segments(parent, segments),
+    }
+}
+
+/// Where the key at `path` is written in the document
+fn key_span(document: &Document<&str>, path: &[String]) -> Option<Range<usize>> {
+    let (last, parents) = path.split_last()?;
+    let mut item = document.as_item();
+    for segment in parents {
+        item = match segment.parse::<usize>() {
+            Ok(index) => item.get(index)?,
+            Err(_) => item.get(segment)?,
+        };
+    }
+    let (key, value) = item.as_table_like()?.get_key_value(last)?;
+    key.span().or_else(|| value.span())
+}
+
+/// Checks the content of a config file read as the `origin` layer: its TOML
+/// syntax, keys lilac doesn't know and values of the wrong type. Each top-level
+/// key is checked on its own, so a bad value only hides problems in its own
+/// table. Issues are sorted by line.
+pub fn validate(content: &str, origin: ConfigOrigin) -> Vec<ConfigIssue> {
+    let issue = |span: Option<Range<usize>>, message: &str| ConfigIssue {
+        line: span.map_or(1, |span| line_of(content, span.start)),
+        message: message.trim().to_string(),
+    };
+    let (document, root) = match (Document::parse(content), DeTable::parse(content)) {
+        (Ok(document), Ok(root)) => (document, root),
+        (Err(e), _) => return vec![issue(e.span(), e.message())],
+        (_, Err(e)) => return vec![issue(e.span(), e.message())],
+    };
+
+    let mut issues = Vec::new();
+    for (key, value) in root.get_ref() {
+        if key.get_ref() == "locked" && origin != ConfigOrigin::SystemFile {
+            issues.push(issue(Some(key.span()), "`locked` only applies in the system config"));
+            continue;
+        }
+
+        let mut table = DeTable::new();
+        table.insert(key.clone(), value.clone());
+        let deserializer = toml::Deserializer::from(Spanned::new(root.span(), table));
+
+        let mut unknown = Vec::new();
+        let result = serde_ignored::deserialize::<_, _, AppConfig>(deserializer, |path| {
+            let mut segments = Vec::new();
+            path_segments(&path, &mut segments);
+            unknown.push(segments);
+        });
+
+        for path in unknown {
+            issues.push(issue(key_span(&document, &path), &format!("unknown key `{}`", path.join("."))));
+        }
+        if let Err(e) = result {
+            issues.push(issue(e.span(), e.message()));
+        }
+    }
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// Reads a value given on the command line. Anything that isn't valid TOML is
+/// taken as a string, and list settings also take comma-separated items.
+fn parse_value(key: &str, raw: &str) -> Value {
+    let value = raw.parse::<Value>().unwrap_or_else(|_| raw.into());
+    if is_list_key(key) && !value.is_array() {
+        let items: Array = raw.split(',').map(str::trim).filter(|item| !item.is_empty()).collect();
+        return Value::Array(items);
+    }
+    value
+}
+
+fn insert_value(document: &mut DocumentMut, key: &str, value: Value) -> Result<(), ConfigError> {
+    let segments: Vec<&str> = key.split('.').collect();
+    let Some((last, parents)) = segments.split_last() else {
+        return Err(ConfigError::Message("Empty config key".into()));
+    };
+
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for segment in parents {
+        let item = table.entry(segment).or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        });
+        table = item.as_table_like_mut()
+            .ok_or_else(|| ConfigError::Message(format!("{} is not a table in {}", segment, key)))?;
+    }
+
+    match table.get_mut(last) {
+        // Keep the comment after the old value
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        _ => {
+            table.insert(last, Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+/// Sets a dotted key in the content of a config file, keeping its comments and
+/// layout. The result is validated as the `origin` layer before it is returned.
+pub fn set_value(content: &str, key: &str, raw: &str, origin: ConfigOrigin) -> Result<String, ConfigError> {
+    let document: DocumentMut = content.parse()
+        .map_err(|e: toml_edit::TomlError| ConfigError::Message(format!("Invalid config: {}", e.message().trim())))?;
+
+    // `8` for a string setting is meant as the string "8"
+    let mut candidates = vec![parse_value(key, raw)];
+    if !candidates[0].is_str() {
+        candidates.push(raw.into());
+    }
+
+    let mut first_issues = None;
+    for value in candidates {
+        let mut edited = document.clone();
+        insert_value(&mut edited, key, value)?;
+        let edited = edited.to_string();
+        let issues = validate(&edited, origin);
+        if issues.is_empty() {
+            return Ok(edited);
+        }
+        first_issues.get_or_insert(issues);
+    }
+
+    let issues = first_issues.unwrap_or_default();
+    let messages: Vec<String> = issues.iter().map(|issue| issue.message.clone()).collect();
+    Err(ConfigError::Message(format!("Can't set {}: {}", key, messages.join(", "))))
+}
+
+fn flatten(prefix: &str, value: &toml::Value, settings: &mut Vec<(String, toml::Value)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, settings);
+            }
+        }
+        value => settings.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// Every setting with a value in the merged config, along with the layer it
+/// comes from. Arrays, such as `sources`, are a single setting.
+pub fn effective_settings(config: &AppConfig, layers: &ConfigLayers) -> Result<Vec<Setting>, ConfigError> {
+    let table = toml::Value::try_from(config)
+        .map_err(|e| ConfigError::Message(format!("Failed to serialize config: {}", e)))?;
+
+    let mut flat = Vec::new();
+    flatten("", &table, &mut flat);
+    Ok(flat.into_iter()
+        .map(|(key, value)| Setting { origin: layers.origin(&key), locked: layers.is_locked(&key), key, value })
+        .collect())
+}
+
+/// The merged value of a dotted key, which may also name a table such as `build`
+pub fn effective_value(config: &AppConfig, key: &str) -> Result<Option<toml::Value>, ConfigError> {
+    let table = toml::Value::try_from(config)
+        .map_err(|e| ConfigError::Message(format!("Failed to serialize config: {}", e)))?;
+    Ok(key.split('.')
+        .try_fold(&table, |value, segment| value.get(segment))
+        .cloned())
+}
@@ -8,6 +8,10 @@ pub enum AurError {
     ParseError(String),
     NotFound(String),
     ApiError(String),
+    /// The `error` field of an RPC response
+    RpcError(String),
+    /// HTTP 429 that retrying didn't get past
+    RateLimited(String),
 }
 
 /// Build module errors
@@ -35,6 +39,8 @@ impl fmt::Display for AurError {
             AurError::ParseError(e) => write!(f, "Failed to parse AUR response: {}", e),
             AurError::NotFound(e) => write!(f, "Package not found in AUR: {}", e),
             AurError::ApiError(e) => write!(f, "AUR API error: {}", e),
+            AurError::RpcError(e) => write!(f, "AUR RPC error: {}", e),
+            AurError::RateLimited(e) => write!(f, "Rate limited by the AUR: {}", e),
         }
     }
 }
@@ -80,6 +86,10 @@ pub fn aur_api_error(e: impl Into<String>) -> AurError {
     AurError::ApiError(e.into())
 }
 
+pub fn aur_rpc_error(e: impl Into<String>) -> AurError {
+    AurError::RpcError(e.into())
+}
+
 pub fn alpm_init_error(e: impl Into<String>) -> AlpmError {
     AlpmError::InitError(e.into())
 }
@@ -0,0 +1,277 @@
+use colored::Colorize;
+use std::ffi::{OsStr, OsString};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use crate::output;
+use crate::progress;
+
+/// How the output of an invocation is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Shown on the terminal
+    #[default]
+    Inherit,
+    /// Thrown away
+    Null,
+    /// Collected into `ExecOutput`
+    Capture,
+}
+
+/// An external command, kept as data so it can be printed instead of run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<OsString>,
+    /// Variables set on top of the inherited environment
+    pub env: Vec<(String, String)>,
+    pub dir: Option<PathBuf>,
+    pub output: OutputMode,
+    /// Written to the program's stdin, such as the text handed to a pager
+    pub input: Option<Vec<u8>>,
+}
+
+impl Invocation {
+    pub fn new(program: &str) -> Self {
+        Invocation {
+            program: program.to_string(),
+            args: Vec::new(),
+            env: Vec::new(),
+            dir: None,
+            output: OutputMode::Inherit,
+            input: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn input(mut self, input: &[u8]) -> Self {
+        self.input = Some(input.to_vec());
+        self
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{}={} ", key, value)?;
+        }
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        if let Some(dir) = &self.dir {
+            write!(f, " (in {})", dir.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of an invocation. Output is only collected in `OutputMode::Capture`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOutput {
+    pub success: bool,
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ExecOutput {
+    pub fn ok() -> Self {
+        ExecOutput { success: true, code: Some(0), ..Default::default() }
+    }
+}
+
+impl fmt::Display for ExecOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "exit status: {}", code),
+            None => write!(f, "terminated by signal"),
+        }
+    }
+}
+
+/// Runs the sudo, pacman and makepkg calls made by the builder and the ALPM wrapper
+pub trait CommandRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<ExecOutput>;
+
+    /// Whether calls are only printed. Steps that don't go through `run`, such as
+    /// git clones and cache writes, are skipped too.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    /// Prints a step that is skipped in dry-run mode, such as a git clone
+    fn announce(&self, step: &str) {
+        progress!("{} {}", "[dry-run]".bright_yellow().bold(), step);
+    }
+}
+
+/// Runs commands on the host. In dry-run mode the calls are printed and reported
+/// as successful without running anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner {
+    dry_run: bool,
+}
+
+impl SystemRunner {
+    pub fn new(dry_run: bool) -> Self {
+        SystemRunner { dry_run }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<ExecOutput> {
+        if self.dry_run {
+            self.announce(&invocation.to_string());
+            return Ok(ExecOutput::ok());
+        }
+
+        let mut command = Command::new(&invocation.program);
+        command.args(&invocation.args).envs(invocation.env.iter().map(|(key, value)| (key, value)));
+        if let Some(dir) = &invocation.dir {
+            command.current_dir(dir);
+        }
+
+        if let Some(input) = &invocation.input {
+            command.stdin(Stdio::piped());
+            match invocation.output {
+                OutputMode::Capture => {
+                    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                }
+                OutputMode::Null => {
+                    command.stdout(Stdio::null()).stderr(Stdio::null());
+                }
+                OutputMode::Inherit => {}
+            }
+            let mut child = command.spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                // The program closing its input early, such as a pager quit with `q`, is not an error
+                let _ = stdin.write_all(input);
+            }
+            let output = child.wait_with_output()?;
+            return Ok(ExecOutput {
+                success: output.status.success(),
+                code: output.status.code(),
+                stdout: output.stdout,
+                stderr: output.stderr,
+            });
+        }
+
+        match invocation.output {
+            OutputMode::Capture => {
+                let output = command.output()?;
+                Ok(ExecOutput {
+                    success: output.status.success(),
+                    code: output.status.code(),
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                })
+            }
+            mode => {
+                if mode == OutputMode::Null {
+                    command.stdout(Stdio::null()).stderr(Stdio::null());
+                } else if output::progress_on_stderr() {
+                    // Keeps makepkg and pacman from writing into a JSON result
+                    command.stdout(io::stderr());
+                }
+                let status = command.status()?;
+                Ok(ExecOutput {
+                    success: status.success(),
+                    code: status.code(),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+type Hook = Box<dyn Fn(&Invocation) + Send>;
+
+#[derive(Default)]
+struct FakeState {
+    calls: Vec<Invocation>,
+    responses: VecDeque<ExecOutput>,
+    hook: Option<Hook>,
+}
+
+/// Scripted runner for tests. Every invocation is recorded, and answered with the
+/// next queued response or with success once the queue is empty. Clones share the
+/// same script and record, so a clone can be handed to the code under test.
+#[derive(Clone, Default)]
+pub struct FakeRunner {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the result of the next unanswered invocation
+    pub fn respond(&self, output: ExecOutput) -> &Self {
+        self.state.lock().unwrap().responses.push_back(output);
+        self
+    }
+
+    /// Runs `hook` on every invocation, such as to drop the package files a
+    /// makepkg call would have produced
+    pub fn on_run(&self, hook: impl Fn(&Invocation) + Send + 'static) -> &Self {
+        self.state.lock().unwrap().hook = Some(Box::new(hook));
+        self
+    }
+
+    pub fn calls(&self) -> Vec<Invocation> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// The recorded invocations as command lines
+    pub fn command_lines(&self) -> Vec<String> {
+        self.calls().iter().map(ToString::to_string).collect()
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<ExecOutput> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(invocation.clone());
+        if let Some(hook) = &state.hook {
+            hook(invocation);
+        }
+        Ok(state.responses.pop_front().unwrap_or_else(ExecOutput::ok))
+    }
+}
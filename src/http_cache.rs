@@ -0,0 +1,109 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A response stored on disk, with the validators needed to revalidate it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    /// Unix time of the last fetch or successful revalidation
+    pub fetched_at: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+
+impl CachedResponse {
+    pub fn new(url: &str, etag: Option<String>, last_modified: Option<String>, body: String) -> Self {
+        CachedResponse { url: url.to_string(), fetched_at: now(), etag, last_modified, body }
+    }
+
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.fetched_at) < ttl.as_secs()
+    }
+
+    /// The same entry, fresh again after the server answered 304 Not Modified
+    pub fn revalidated(self) -> Self {
+        CachedResponse { fetched_at: now(), ..self }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Stable across runs and toolchains, unlike `DefaultHasher`
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Cache of AUR responses keyed by request URL. Bodies are memoized for the rest
+/// of the run, and stored on disk for `ttl` when a directory is set. Refreshing
+/// skips disk entries but still stores the new responses.
+pub struct ResponseCache {
+    dir: Option<PathBuf>,
+    ttl: Duration,
+    refresh: bool,
+    memo: Mutex<HashMap<String, String>>,
+}
+
+impl ResponseCache {
+    /// Memoization only, nothing is written to disk
+    pub fn in_memory() -> Self {
+        ResponseCache {
+            dir: None,
+            ttl: Duration::ZERO,
+            refresh: false,
+            memo: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn on_disk(dir: PathBuf, ttl: Duration, refresh: bool) -> Self {
+        ResponseCache { dir: Some(dir), ttl, refresh, ..Self::in_memory() }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn memoized(&self, url: &str) -> Option<String> {
+        self.memo.lock().unwrap().get(url).cloned()
+    }
+
+    pub fn memoize(&self, url: &str, body: &str) {
+        self.memo.lock().unwrap().insert(url.to_string(), body.to_string());
+    }
+
+    fn entry_path(&self, url: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{:016x}.json", fnv1a(url))))
+    }
+
+    /// The stored response for a URL, fresh or not. Nothing is returned when
+    /// refreshing, or when the entry belongs to another URL with the same hash.
+    pub fn load(&self, url: &str) -> Option<CachedResponse> {
+        if self.refresh {
+            return None;
+        }
+        let content = fs::read_to_string(self.entry_path(url)?).ok()?;
+        serde_json::from_str::<CachedResponse>(&content).ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    /// Writes an entry to disk. Failing to cache never fails the request.
+    pub fn store(&self, entry: &CachedResponse) {
+        let (Some(dir), Some(path)) = (&self.dir, self.entry_path(&entry.url)) else {
+            return;
+        };
+        let result = fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&path, serde_json::to_vec(entry).unwrap_or_default()));
+        if let Err(e) = result {
+            debug!("Failed to cache {}: {}", entry.url, e);
+        }
+    }
+}
@@ -2,13 +2,25 @@ pub mod alpm;
 pub mod aur;
 pub mod build;
 pub mod config;
+pub mod config_file;
 pub mod error;
+pub mod exec;
+pub mod http_cache;
+pub mod local_aur;
 pub mod logging;
+pub mod network;
 pub mod commands;
+pub mod srcinfo;
+pub mod output;
+pub mod search;
+pub mod sources;
+pub mod tree;
 
 pub use alpm::AlpmWrapper;
-pub use aur::AurClient;
+pub use aur::{AurBackend, AurClient};
 pub use build::PackageBuilder;
 pub use config::AppConfig;
 pub use error::{AlpmError, AurError, BuildError};
+pub use local_aur::{FixtureAur, MockAur, SnapshotAur};
 pub use logging::init_logger;
+pub use srcinfo::SrcInfo;
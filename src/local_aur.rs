@@ -0,0 +1,300 @@
+use crate::aur::{AurBackend, AurPackage, AurResponse, SearchField};
+use crate::error::{AurError, BuildError, aur_api_error, aur_parse_error, aur_request_failed, build_git_error};
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use git2::Repository;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Matches a search term the way the RPC does for the fields that can be answered
+/// from package metadata alone
+fn search_local(packages: &[AurPackage], query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError> {
+    let query = query.to_lowercase();
+    let contains = |text: &str| text.to_lowercase().contains(&query);
+
+    let matches = |pkg: &AurPackage| match by {
+        SearchField::Name => Ok(contains(&pkg.name)),
+        SearchField::NameDesc => Ok(contains(&pkg.name) || pkg.description.as_deref().is_some_and(contains)),
+        SearchField::Maintainer => Ok(pkg.maintainer.as_deref().is_some_and(|m| m.to_lowercase() == query)),
+        other => Err(aur_api_error(format!("Searching by {} is not supported offline", other.as_str()))),
+    };
+
+    let mut results = Vec::new();
+    for pkg in packages {
+        if matches(pkg)? {
+            results.push(pkg.clone());
+        }
+    }
+    Ok(results)
+}
+
+fn find_package(packages: &[AurPackage], package_name: &str) -> Result<AurPackage, AurError> {
+    packages.iter()
+        .find(|pkg| pkg.name == package_name)
+        .cloned()
+        .ok_or_else(|| AurError::NotFound(package_name.to_string()))
+}
+
+fn find_packages(packages: &[AurPackage], package_names: &[String]) -> Vec<AurPackage> {
+    packages.iter()
+        .filter(|pkg| package_names.contains(&pkg.name))
+        .cloned()
+        .collect()
+}
+
+/// AUR served from a directory, for working offline and for tests. The directory
+/// holds `packages.json`, an RPC response listing every package, and one git repo
+/// per pkgbase under `repos/`.
+pub struct FixtureAur {
+    root: PathBuf,
+    packages: Vec<AurPackage>,
+}
+
+impl FixtureAur {
+    pub fn open(root: &Path) -> Result<Self, AurError> {
+        let index = root.join("packages.json");
+        let content = fs::read_to_string(&index)
+            .map_err(|e| aur_request_failed(format!("Failed to read {:?}: {}", index, e)))?;
+        let response: AurResponse = serde_json::from_str(&content)
+            .map_err(|e| aur_parse_error(e.to_string()))?;
+
+        Ok(FixtureAur { root: root.to_path_buf(), packages: response.results })
+    }
+
+    fn repo_path(&self, pkgbase: &str) -> PathBuf {
+        self.root.join("repos").join(pkgbase)
+    }
+}
+
+#[async_trait]
+impl AurBackend for FixtureAur {
+    async fn search_packages_by(&self, query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError> {
+        search_local(&self.packages, query, by)
+    }
+
+    async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError> {
+        find_package(&self.packages, package_name)
+    }
+
+    async fn get_packages_info(&self, package_names: &[String]) -> Result<Vec<AurPackage>, AurError> {
+        Ok(find_packages(&self.packages, package_names))
+    }
+
+    /// Reads the file from the repo's HEAD commit, not its working tree
+    async fn get_repo_file(&self, pkgbase: &str, file: &str) -> Result<String, AurError> {
+        let not_found = || AurError::NotFound(format!("{} in {}", file, pkgbase));
+
+        let repo = Repository::open(self.repo_path(pkgbase)).map_err(|_| not_found())?;
+        let tree = repo.head()
+            .and_then(|head| head.peel_to_tree())
+            .map_err(|e| aur_request_failed(format!("Failed to read {}: {}", pkgbase, e)))?;
+        let entry = tree.get_path(Path::new(file)).map_err(|_| not_found())?;
+        let object = entry.to_object(&repo)
+            .map_err(|e| aur_request_failed(format!("Failed to read {}: {}", file, e)))?;
+        let blob = object.as_blob().ok_or_else(not_found)?;
+
+        String::from_utf8(blob.content().to_vec()).map_err(|e| aur_parse_error(e.to_string()))
+    }
+
+    fn clone_repo(&self, pkgbase: &str, dest: &Path) -> Result<bool, BuildError> {
+        let repo_path = self.repo_path(pkgbase);
+        if !repo_path.exists() {
+            return Ok(false);
+        }
+        Repository::clone(&repo_path.to_string_lossy(), dest)
+            .map_err(|e| build_git_error(format!("Git clone failed: {}", e), pkgbase))?;
+        Ok(true)
+    }
+}
+
+/// In-memory AUR for tests. Cloning a pkgbase writes its files into a fresh git
+/// repo, without any commits.
+#[derive(Default)]
+pub struct MockAur {
+    packages: Vec<AurPackage>,
+    repo_files: HashMap<String, Vec<(String, String)>>,
+}
+
+impl MockAur {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_package(mut self, package: AurPackage) -> Self {
+        self.packages.push(package);
+        self
+    }
+
+    pub fn with_repo_file(mut self, pkgbase: &str, file: &str, content: &str) -> Self {
+        self.repo_files.entry(pkgbase.to_string())
+            .or_default()
+            .push((file.to_string(), content.to_string()));
+        self
+    }
+}
+
+#[async_trait]
+impl AurBackend for MockAur {
+    async fn search_packages_by(&self, query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError> {
+        search_local(&self.packages, query, by)
+    }
+
+    async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError> {
+        find_package(&self.packages, package_name)
+    }
+
+    async fn get_packages_info(&self, package_names: &[String]) -> Result<Vec<AurPackage>, AurError> {
+        Ok(find_packages(&self.packages, package_names))
+    }
+
+    async fn get_repo_file(&self, pkgbase: &str, file: &str) -> Result<String, AurError> {
+        self.repo_files.get(pkgbase)
+            .and_then(|files| files.iter().find(|(name, _)| name == file))
+            .map(|(_, content)| content.clone())
+            .ok_or_else(|| AurError::NotFound(format!("{} in {}", file, pkgbase)))
+    }
+
+    fn clone_repo(&self, pkgbase: &str, dest: &Path) -> Result<bool, BuildError> {
+        let Some(files) = self.repo_files.get(pkgbase) else {
+            return Ok(false);
+        };
+
+        Repository::init(dest)
+            .map_err(|e| build_git_error(format!("Git init failed: {}", e), pkgbase))?;
+        for (name, content) in files {
+            fs::write(dest.join(name), content)
+                .map_err(|e| build_git_error(format!("Failed to write {}: {}", name, e), pkgbase))?;
+        }
+        Ok(true)
+    }
+}
+
+/// Entry of the metadata archive, which carries dependency lists on top of the
+/// fields the RPC search returns
+#[derive(Debug, Deserialize)]
+struct MetaEntry {
+    #[serde(flatten)]
+    package: AurPackage,
+    #[serde(rename = "Depends", default)]
+    depends: Vec<String>,
+    #[serde(rename = "MakeDepends", default)]
+    makedepends: Vec<String>,
+    #[serde(rename = "CheckDepends", default)]
+    checkdepends: Vec<String>,
+}
+
+/// AUR answered from the `packages-meta-ext-v1.json.gz` snapshot saved by
+/// `lilac sync-meta`. PKGBUILD repos can't be reached, but a .SRCINFO carrying
+/// the version and dependencies is rebuilt from the metadata.
+pub struct SnapshotAur {
+    packages: Vec<AurPackage>,
+    entries: HashMap<String, MetaEntry>,
+    /// Package names of each pkgbase, sorted
+    bases: HashMap<String, Vec<String>>,
+}
+
+impl SnapshotAur {
+    pub fn open(path: &Path) -> Result<Self, AurError> {
+        let file = File::open(path)
+            .map_err(|e| aur_request_failed(format!("Failed to open {:?}: {}", path, e)))?;
+        let entries: Vec<MetaEntry> = serde_json::from_reader(BufReader::new(GzDecoder::new(file)))
+            .map_err(|e| aur_parse_error(format!("{:?}: {}", path, e)))?;
+
+        let mut bases: HashMap<String, Vec<String>> = HashMap::new();
+        for entry in &entries {
+            let pkgbase = entry.package.package_base.as_deref().unwrap_or(&entry.package.name);
+            bases.entry(pkgbase.to_string()).or_default().push(entry.package.name.clone());
+        }
+        for members in bases.values_mut() {
+            members.sort();
+        }
+
+        Ok(SnapshotAur {
+            packages: entries.iter().map(|entry| entry.package.clone()).collect(),
+            entries: entries.into_iter().map(|entry| (entry.package.name.clone(), entry)).collect(),
+            bases,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// .SRCINFO of a pkgbase as far as the metadata knows it. Make and check
+    /// dependencies go in the pkgbase section, runtime ones in each package's.
+    fn srcinfo(&self, pkgbase: &str) -> Option<String> {
+        let members: Vec<&MetaEntry> = self.bases.get(pkgbase)?.iter()
+            .filter_map(|name| self.entries.get(name))
+            .collect();
+        let first = members.first()?;
+
+        let (epoch, version) = match first.package.version.split_once(':') {
+            Some((epoch, rest)) => (Some(epoch), rest),
+            None => (None, first.package.version.as_str()),
+        };
+        let (pkgver, pkgrel) = version.rsplit_once('-').unwrap_or((version, "1"));
+
+        let mut srcinfo = format!("pkgbase = {}\n\tpkgver = {}\n\tpkgrel = {}\n", pkgbase, pkgver, pkgrel);
+        if let Some(epoch) = epoch {
+            srcinfo.push_str(&format!("\tepoch = {}\n", epoch));
+        }
+        for entry in &members {
+            for dep in &entry.makedepends {
+                srcinfo.push_str(&format!("\tmakedepends = {}\n", dep));
+            }
+            for dep in &entry.checkdepends {
+                srcinfo.push_str(&format!("\tcheckdepends = {}\n", dep));
+            }
+        }
+        for entry in &members {
+            srcinfo.push_str(&format!("\npkgname = {}\n", entry.package.name));
+            if entry.depends.is_empty() {
+                srcinfo.push_str("\tdepends = \n");
+            }
+            for dep in &entry.depends {
+                srcinfo.push_str(&format!("\tdepends = {}\n", dep));
+            }
+        }
+        Some(srcinfo)
+    }
+}
+
+#[async_trait]
+impl AurBackend for SnapshotAur {
+    async fn search_packages_by(&self, query: &str, by: SearchField) -> Result<Vec<AurPackage>, AurError> {
+        search_local(&self.packages, query, by)
+    }
+
+    async fn get_package_info(&self, package_name: &str) -> Result<AurPackage, AurError> {
+        self.entries.get(package_name)
+            .map(|entry| entry.package.clone())
+            .ok_or_else(|| AurError::NotFound(package_name.to_string()))
+    }
+
+    async fn get_packages_info(&self, package_names: &[String]) -> Result<Vec<AurPackage>, AurError> {
+        Ok(package_names.iter()
+            .filter_map(|name| self.entries.get(name))
+            .map(|entry| entry.package.clone())
+            .collect())
+    }
+
+    async fn get_repo_file(&self, pkgbase: &str, file: &str) -> Result<String, AurError> {
+        let srcinfo = self.srcinfo(pkgbase)
+            .ok_or_else(|| AurError::NotFound(format!("{} in {}", file, pkgbase)))?;
+        if file != ".SRCINFO" {
+            return Err(aur_api_error(format!("Only .SRCINFO is available offline, not {}", file)));
+        }
+        Ok(srcinfo)
+    }
+
+    fn clone_repo(&self, pkgbase: &str, _dest: &Path) -> Result<bool, BuildError> {
+        Err(build_git_error("PKGBUILD repos can't be cloned in offline mode", pkgbase))
+    }
+}
@@ -1,35 +1,85 @@
 use lilac_aur::{
     AlpmWrapper,
+    AurBackend,
     AurClient,
     AppConfig,
+    SnapshotAur,
     init_logger,
-    commands::{Commands, handle_command}
+    exec::SystemRunner,
+    http_cache::ResponseCache,
+    output,
+    commands::{Commands, GlobalOpts, handle_command, handle_config, interactive_install}
 };
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{CommandFactory, Parser};
 use log::debug;
 use colored::Colorize;
+use std::time::Duration;
 
 
+// No `args_conflicts_with_subcommands`: clap counts global flags as arguments, so
+// `lilac --dry-run install foo` would search for "install foo". Bare terms still
+// can't be mixed with a subcommand, since the terms take every value after them.
 #[derive(Parser)]
 #[command(version)]
 struct Cli {
+    #[command(flatten)]
+    opts: GlobalOpts,
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+    /// Search the repos and the AUR, then pick packages to install by number
+    terms: Vec<String>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    if cli.command.is_none() && cli.terms.is_empty() {
+        Cli::command().print_help()?;
+        return Ok(());
+    }
+    if !cli.opts.format.colored() {
+        colored::control::set_override(false);
+    }
+    output::route_progress(cli.opts.format);
+
     init_logger();
-    let config = AppConfig::load()?;
-    debug!("{}\n", "Configuration loaded".bright_green());
+    if let Some(Commands::Config { action }) = cli.command {
+        return handle_config(action, &cli.opts, &SystemRunner::new(cli.opts.dry_run));
+    }
 
-    let aur = AurClient::new(config.aur_base_url.clone());
-    let alpm = AlpmWrapper::new()?;
+    // Checked before the snapshot is opened, which fails first when it is missing
+    if cli.opts.offline && matches!(cli.command, Some(Commands::SyncMeta)) {
+        anyhow::bail!("sync-meta downloads the snapshot and can't run with --offline");
+    }
 
-    let cli = Cli::parse();
+    let config = AppConfig::load_with(&cli.opts.config_overrides())?;
+    debug!("{}\n", "Configuration loaded".bright_green());
+    config.network_settings().configure_git()?;
+
+    let aur: Box<dyn AurBackend> = if cli.opts.offline {
+        let path = config.meta_archive_path()?;
+        let snapshot = SnapshotAur::open(&path)
+            .with_context(|| format!("No usable AUR snapshot at {:?}, run `lilac sync-meta` first", path))?;
+        debug!("Offline with {} packages from {:?}", snapshot.len(), path);
+        Box::new(snapshot)
+    } else {
+        let cache = ResponseCache::on_disk(
+            config.rpc_cache_path()?,
+            Duration::from_secs(config.rpc_cache_ttl),
+            cli.opts.refresh,
+        );
+        Box::new(AurClient::with_options(config.aur_base_url.clone(), config.client_options())?.with_cache(cache))
+    };
+    let alpm = AlpmWrapper::new()?
+        .with_runner(SystemRunner::new(cli.opts.dry_run))
+        .with_pacman_options(config.pacman_options());
 
-    handle_command(cli.command, &config, &aur, &alpm).await?;
+    match cli.command {
+        Some(command) => handle_command(command, &cli.opts, &config, aur.as_ref(), &alpm).await?,
+        None => interactive_install(cli.terms, cli.opts.format, &config, aur.as_ref(), &alpm).await?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,106 @@
+use config::ConfigError;
+use git2::{FetchOptions, ProxyOptions};
+use reqwest::{Certificate, ClientBuilder, NoProxy, Proxy};
+use std::ffi::{CString, c_int};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+pub const DEFAULT_USER_AGENT: &str = concat!("lilac/", env!("CARGO_PKG_VERSION"));
+
+/// Proxy given to git fetches, set once by `NetworkSettings::configure_git`
+static GIT_PROXY: OnceLock<Option<String>> = OnceLock::new();
+
+/// Proxy, CA and user agent settings applied to both the HTTP client and git
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkSettings {
+    /// Proxy for all traffic, with any credentials in the URL. Without it the
+    /// `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` variables apply,
+    /// and git's `http.proxy` for clones.
+    pub proxy: Option<String>,
+    /// PEM bundles trusted on top of the system certificates
+    pub ca_certificates: Vec<PathBuf>,
+    pub user_agent: String,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            proxy: None,
+            ca_certificates: Vec::new(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+impl NetworkSettings {
+    /// Applies the settings to a reqwest client
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, ConfigError> {
+        builder = builder.user_agent(&self.user_agent);
+
+        if let Some(url) = &self.proxy {
+            let proxy = Proxy::all(url)
+                .map_err(|e| ConfigError::Message(format!("Invalid proxy {}: {}", url, e)))?;
+            // An explicit proxy still skips the hosts listed in NO_PROXY
+            builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+        }
+
+        for path in &self.ca_certificates {
+            let pem = fs::read(path).map_err(|e| {
+                ConfigError::Message(format!("Failed to read CA bundle {}: {}", path.display(), e))
+            })?;
+            let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| {
+                ConfigError::Message(format!("Invalid CA bundle {}: {}", path.display(), e))
+            })?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Applies the settings to libgit2. Its CA and user agent settings are global
+    /// to the process, so only the first call takes effect. The proxy is picked up
+    /// by `fetch_options`.
+    pub fn configure_git(&self) -> Result<(), ConfigError> {
+        if GIT_PROXY.set(self.proxy.clone()).is_err() {
+            return Ok(());
+        }
+
+        let user_agent = CString::new(self.user_agent.as_str())
+            .map_err(|e| ConfigError::Message(format!("Invalid user agent: {}", e)))?;
+        libgit2_sys::init();
+        // SAFETY: libgit2 is initialized and copies the string before returning
+        let result = unsafe {
+            libgit2_sys::git_libgit2_opts(libgit2_sys::GIT_OPT_SET_USER_AGENT as c_int, user_agent.as_ptr())
+        };
+        if result < 0 {
+            return Err(ConfigError::Message("Failed to set the git user agent".into()));
+        }
+
+        for path in &self.ca_certificates {
+            // SAFETY: called before any git transfer, so no connection reads the
+            // certificate store while it changes. Each bundle adds to the store.
+            unsafe { git2::opts::set_ssl_cert_file(path) }.map_err(|e| {
+                ConfigError::Message(format!("Failed to load CA bundle {} for git: {}", path.display(), e))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fetch options using the configured proxy, or the one git config or the
+/// environment point to
+pub fn fetch_options<'a>() -> FetchOptions<'a> {
+    let mut proxy = ProxyOptions::new();
+    match GIT_PROXY.get().and_then(Option::as_deref) {
+        Some(url) => proxy.url(url),
+        None => proxy.auto(),
+    };
+
+    let mut options = FetchOptions::new();
+    options.proxy_options(proxy);
+    options
+}
@@ -0,0 +1,420 @@
+use crate::alpm::RepoPackage;
+use crate::aur::AurPackage;
+use crate::build::{BuildSummary, BuiltPackage, CachedPackage, PackageBuilder};
+use crate::commands::RemovalPlan;
+use crate::config_file::Setting;
+use crate::search::{FoundPackage, SearchResult};
+use crate::tree::{DepNode, LinkKind, NodeSource, WhyLink};
+use chrono::{TimeZone, Utc};
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How read-only commands print their results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored text for terminals
+    #[default]
+    Human,
+    /// The human layout without colors
+    Plain,
+    /// Machine readable JSON on stdout
+    Json,
+}
+
+impl OutputFormat {
+    /// Whether ANSI colors may be written for this format
+    pub fn colored(self) -> bool {
+        self == OutputFormat::Human
+    }
+}
+
+static PROGRESS_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends progress messages, and the output of the commands lilac runs, to stderr
+/// for every format but the human one, so stdout only carries the result
+pub fn route_progress(format: OutputFormat) {
+    PROGRESS_ON_STDERR.store(format != OutputFormat::Human, Ordering::Relaxed);
+}
+
+pub fn progress_on_stderr() -> bool {
+    PROGRESS_ON_STDERR.load(Ordering::Relaxed)
+}
+
+/// `println!` for progress messages, which go where `route_progress` sent them
+#[macro_export]
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::output::progress_on_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `lilac info` result for an AUR package
+#[derive(Debug, Serialize)]
+pub struct PackageInfo<'a> {
+    pub source: &'a str,
+    pub installed_version: Option<String>,
+    #[serde(flatten)]
+    pub package: &'a AurPackage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dependencies: Option<Vec<String>>,
+}
+
+/// `lilac info` result for a package from a sync database
+#[derive(Debug, Serialize)]
+pub struct RepoPackageInfo<'a> {
+    pub source: &'a str,
+    pub installed_version: Option<String>,
+    #[serde(flatten)]
+    pub package: &'a RepoPackage,
+}
+
+/// A cached package whose AUR version is newer, as reported by `lilac outdated`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutdatedPackage {
+    pub name: String,
+    pub cached_version: String,
+    pub latest_version: String,
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn print_search_results(results: &[SearchResult], format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(results);
+    }
+
+    for result in results {
+        println!(
+            "\n{}: {}/{}",
+            "Name".bold(),
+            result.source.bright_magenta(),
+            result.package.name().bright_green()
+        );
+        println!("{}: {}", "Version".bold(), result.package.version().bright_cyan());
+        if let Some(installed) = &result.installed_version {
+            println!("{}: {}", "Installed".bold(), installed.bright_yellow());
+        }
+        if let Some(desc) = result.package.description() {
+            println!("{}: {}", "Description".bold(), desc);
+        }
+        if let FoundPackage::Aur(pkg) = &result.package {
+            if let Some(url) = &pkg.url {
+                println!("{}: {}", "URL".bold(), url);
+            }
+            if let Some(maintainer) = &pkg.maintainer {
+                println!("{}: {}", "Maintainer".bold(), maintainer);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Numbered one-line-per-result listing used for interactive selection
+pub fn print_numbered_results(results: &[SearchResult]) {
+    for (index, result) in results.iter().enumerate() {
+        let installed = match &result.installed_version {
+            Some(version) => format!(" (Installed: {})", version).bright_yellow().to_string(),
+            None => String::new(),
+        };
+        println!(
+            "{} {}/{} {}{}",
+            format!("{:>3}", index + 1).bright_magenta(),
+            result.source.bright_magenta(),
+            result.package.name().bold(),
+            result.package.version().bright_cyan(),
+            installed
+        );
+        if let Some(desc) = result.package.description() {
+            println!("      {}", desc);
+        }
+    }
+}
+
+pub fn print_package_info(info: &PackageInfo, format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(info);
+    }
+
+    let pkg_info = info.package;
+    println!("{}: {}", "\nPackage".bold(), pkg_info.name.green());
+    println!("{}: {}", "Repository".bold(), info.source.bright_magenta());
+    println!("{}: {}", "Version".bold(), pkg_info.version.bright_cyan());
+    if let Some(installed) = &info.installed_version {
+        println!("{}: {}", "Installed".bold(), installed.bright_yellow());
+    }
+    if let Some(desc) = &pkg_info.description {
+        println!("{}: {}", "Description".bold(), desc);
+    }
+    if let Some(url) = &pkg_info.url {
+        println!("{}: {}", "URL".bold(), url);
+    }
+    if let Some(maintainer) = &pkg_info.maintainer {
+        println!("{}: {}", "Maintainer".bold(), maintainer);
+    }
+    println!("{}: {}", "Votes".bold(), pkg_info.num_votes);
+    println!("{}: {}", "Popularity".bold(), pkg_info.popularity);
+    let first_submitted_dt = Utc.timestamp_opt(pkg_info.first_submitted as i64, 0).unwrap();
+    let last_modified_dt = Utc.timestamp_opt(pkg_info.last_modified as i64, 0).unwrap();
+    println!("{}: {}", "First Submitted".bold(), first_submitted_dt.format("%m/%d/%Y"));
+    println!("{}: {}\n", "Last Modified".bold(), last_modified_dt.format("%m/%d/%Y"));
+
+    if let Some(dependencies) = &info.dependencies {
+        if !dependencies.is_empty() {
+            println!("{}:", "Dependencies".bold());
+            for dep in dependencies {
+                println!("  - {}", dep.bright_green());
+            }
+        } else {
+            println!("{}: {}", "Dependencies".bold(), "None found".bright_green());
+        }
+    }
+    Ok(())
+}
+
+pub fn print_repo_package_info(info: &RepoPackageInfo, format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(info);
+    }
+
+    let pkg = info.package;
+    println!("{}: {}", "\nPackage".bold(), pkg.name.green());
+    println!("{}: {}", "Repository".bold(), info.source.bright_magenta());
+    println!("{}: {}", "Version".bold(), pkg.version.bright_cyan());
+    if let Some(installed) = &info.installed_version {
+        println!("{}: {}", "Installed".bold(), installed.bright_yellow());
+    }
+    if let Some(desc) = &pkg.description {
+        println!("{}: {}", "Description".bold(), desc);
+    }
+    if let Some(url) = &pkg.url {
+        println!("{}: {}", "URL".bold(), url);
+    }
+    if let Some(arch) = &pkg.arch {
+        println!("{}: {}", "Architecture".bold(), arch);
+    }
+    if !pkg.licenses.is_empty() {
+        println!("{}: {}", "Licenses".bold(), pkg.licenses.join(" "));
+    }
+    if let Some(packager) = &pkg.packager {
+        println!("{}: {}", "Packager".bold(), packager);
+    }
+    println!("{}: {:.2} MiB", "Installed Size".bold(), pkg.installed_size as f64 / 1024.0 / 1024.0);
+    let build_dt = Utc.timestamp_opt(pkg.build_date, 0).unwrap();
+    println!("{}: {}\n", "Build Date".bold(), build_dt.format("%m/%d/%Y"));
+
+    if pkg.depends.is_empty() {
+        println!("{}: {}", "Dependencies".bold(), "None found".bright_green());
+    } else {
+        println!("{}:", "Dependencies".bold());
+        for dep in &pkg.depends {
+            println!("  - {}", dep.bright_green());
+        }
+    }
+    Ok(())
+}
+
+pub fn print_cached_packages(packages: &[CachedPackage], format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(packages);
+    }
+
+    if packages.is_empty() {
+        println!("\n{}\n", "No packages installed via lilac found in cache.".bold());
+        return Ok(());
+    }
+
+    println!("\n{}\n", "Packages installed via lilac:".bold());
+    for pkg in packages {
+        println!("  - {}", format!("{} ({})", pkg.name, pkg.version).bright_green());
+        if let Some(debug_version) = &pkg.debug_version {
+            println!(
+                "      {} {}",
+                "debug:".bold(),
+                format!("{} ({})", PackageBuilder::debug_package_name(&pkg.pkgbase), debug_version).bright_cyan()
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+pub fn print_outdated(packages: &[OutdatedPackage], format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(packages);
+    }
+
+    if packages.is_empty() {
+        println!("\n{}\n", "All packages are up to date.".bold());
+        return Ok(());
+    }
+
+    println!("\n{}\n", "Outdated packages:".bold());
+    for pkg in packages {
+        println!(
+            "  - {} {} -> {}",
+            pkg.name.bright_green(),
+            pkg.cached_version.bright_cyan(),
+            pkg.latest_version.bright_green()
+        );
+    }
+    println!();
+    Ok(())
+}
+
+/// A config value the way it is written in TOML, with strings left unquoted
+pub fn setting_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+pub fn print_settings(settings: &[Setting], format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(settings);
+    }
+
+    for setting in settings {
+        let origin = if setting.locked {
+            format!("# {}, locked", setting.origin)
+        } else {
+            format!("# {}", setting.origin)
+        };
+        println!("{} = {} {}", setting.key.bold(), setting.value.to_string().bright_cyan(), origin.dimmed());
+    }
+    Ok(())
+}
+
+pub fn print_dep_tree(root: &DepNode, format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(root);
+    }
+
+    println!("{}", describe_node(root));
+    print_children(root, "");
+    Ok(())
+}
+
+fn print_children(node: &DepNode, prefix: &str) {
+    for (index, child) in node.children.iter().enumerate() {
+        let last = index + 1 == node.children.len();
+        let (branch, indent) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        println!("{}{}{}", prefix, branch, describe_node(child));
+        print_children(child, &format!("{}{}", prefix, indent));
+    }
+}
+
+fn describe_node(node: &DepNode) -> String {
+    let mut line = node.name.bold().to_string();
+    if let Some(constraint) = &node.constraint {
+        line.push_str(&constraint.bright_yellow().to_string());
+    }
+    if let Some(provider) = &node.provider {
+        line.push_str(&format!(" (provided by {})", provider.bright_green()));
+    }
+    let source = match (&node.repo, node.source) {
+        (Some(repo), _) => repo.bright_magenta(),
+        (None, NodeSource::Installed) => "installed".bright_cyan(),
+        (None, NodeSource::Cached) => "cached".bright_green(),
+        (None, NodeSource::Aur) => "aur".bright_magenta(),
+        (None, NodeSource::Missing) => "missing".red(),
+        (None, NodeSource::Repo) => "repo".bright_magenta(),
+    };
+    line.push_str(&format!(" [{}]", source));
+    // Expanded further up, like cargo tree does
+    if node.repeated {
+        line.push_str(&" (*)".dimmed().to_string());
+    }
+    line
+}
+
+pub fn print_why(package: &str, chains: &[Vec<WhyLink>], format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(chains);
+    }
+
+    if chains.is_empty() {
+        println!("\n{} {}\n", package.bright_green(), "is not needed by any explicitly installed package.".bold());
+        return Ok(());
+    }
+    if chains.len() == 1 && chains[0].len() == 1 {
+        println!("\n{} {}\n", package.bright_green(), "was installed explicitly.".bold());
+        return Ok(());
+    }
+
+    println!("\n{} {}\n", package.bright_green(), "is installed because:".bold());
+    for chain in chains {
+        let mut line = chain[0].name.bright_green().to_string();
+        for link in &chain[1..] {
+            let arrow = match link.via {
+                Some(LinkKind::LilacRecord) => " <- (lilac) ",
+                _ => " <- ",
+            };
+            line.push_str(arrow);
+            line.push_str(&link.name.bold().to_string());
+        }
+        println!("  {} {}", line, "(explicit)".bright_cyan());
+    }
+    println!();
+    Ok(())
+}
+
+pub fn print_removal_plan(plan: &RemovalPlan, format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(plan);
+    }
+
+    println!("\n{}", "Packages to remove:".bold());
+    for package in &plan.remove {
+        println!("  - {}", package.bright_green());
+    }
+    if !plan.kept.is_empty() {
+        println!("{}", "Recorded dependencies kept:".bold());
+        for dep in &plan.kept {
+            let reason = if dep.explicit {
+                "installed explicitly".to_string()
+            } else {
+                format!("needed by {}", dep.needed_by.join(", "))
+            };
+            println!("  - {} ({})", dep.name.bright_yellow(), reason);
+        }
+    }
+    Ok(())
+}
+
+fn print_built(pkg: &BuiltPackage) {
+    println!(
+        "  - {} {} {} {}",
+        pkg.name.bright_green(),
+        "from".bold(),
+        pkg.source.bright_magenta(),
+        format!("({:?})", pkg.path).bright_cyan()
+    );
+}
+
+pub fn print_build_summary(summary: &BuildSummary, format: OutputFormat) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        return print_json(summary);
+    }
+
+    println!("\n{}", "✓ Built packages:".green().bold());
+    for pkg in &summary.targets {
+        print_built(pkg);
+    }
+    if !summary.dependencies.is_empty() {
+        println!("{}", "AUR dependencies:".bold());
+        for pkg in &summary.dependencies {
+            print_built(pkg);
+        }
+    }
+    Ok(())
+}
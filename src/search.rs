@@ -0,0 +1,196 @@
+use crate::alpm::RepoPackage;
+use crate::aur::{AurPackage, SearchField};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::cmp::Ordering;
+
+/// Source name used for AUR results
+pub const AUR_SOURCE: &str = "aur";
+
+/// A package found in a sync database or in the AUR
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum FoundPackage {
+    Repo(RepoPackage),
+    Aur(AurPackage),
+}
+
+impl FoundPackage {
+    pub fn name(&self) -> &str {
+        match self {
+            FoundPackage::Repo(pkg) => &pkg.name,
+            FoundPackage::Aur(pkg) => &pkg.name,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        match self {
+            FoundPackage::Repo(pkg) => &pkg.version,
+            FoundPackage::Aur(pkg) => &pkg.version,
+        }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            FoundPackage::Repo(pkg) => pkg.description.as_deref(),
+            FoundPackage::Aur(pkg) => pkg.description.as_deref(),
+        }
+    }
+}
+
+/// A search or info result, marked with where it came from and whether it is installed
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    /// Sync database name, or `aur`
+    pub source: String,
+    pub installed_version: Option<String>,
+    #[serde(flatten)]
+    pub package: FoundPackage,
+}
+
+/// Order of search results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// Most voted first
+    Votes,
+    /// Most popular first
+    Popularity,
+    /// Most recently modified first
+    Modified,
+    /// Alphabetical
+    Name,
+}
+
+/// Local filtering applied on top of the RPC search, which only takes one term
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub terms: Vec<String>,
+    /// Field the terms are matched against
+    pub by: SearchField,
+    pub sort: Option<SortKey>,
+    pub limit: Option<usize>,
+    pub hide_out_of_date: bool,
+    pub hide_orphans: bool,
+}
+
+impl SearchFilter {
+    /// The term sent to the RPC. The longest one is the most selective, the rest
+    /// are matched locally.
+    pub fn rpc_term(&self) -> Option<&str> {
+        self.terms.iter()
+            .map(String::as_str)
+            .reduce(|longest, term| if term.len() > longest.len() { term } else { longest })
+    }
+
+    /// Fails when the terms can't all be matched. Search results only carry the
+    /// name, description and maintainer, so the other fields take a single term.
+    pub fn check(&self) -> Result<(), String> {
+        let local = matches!(self.by, SearchField::Name | SearchField::NameDesc | SearchField::Maintainer);
+        if !local && self.terms.len() > 1 {
+            return Err(format!("Searching by {} takes a single term", self.by.as_str()));
+        }
+        Ok(())
+    }
+
+    /// Whether every term not sent to the RPC matches the searched field. Names
+    /// and descriptions contain the term, maintainers are matched exactly like the
+    /// RPC does.
+    pub fn matches_terms(&self, pkg: &AurPackage) -> bool {
+        let rpc_term = self.rpc_term();
+        let name = pkg.name.to_lowercase();
+        let description = pkg.description.as_deref().unwrap_or_default().to_lowercase();
+        let maintainer = pkg.maintainer.as_deref().unwrap_or_default().to_lowercase();
+        let mut skipped_rpc_term = false;
+        self.terms.iter().all(|term| {
+            if !skipped_rpc_term && Some(term.as_str()) == rpc_term {
+                skipped_rpc_term = true;
+                return true;
+            }
+            let term = term.to_lowercase();
+            match self.by {
+                SearchField::Name => name.contains(&term),
+                SearchField::Maintainer => maintainer == term,
+                // Other fields are rejected by `check` with more than one term
+                _ => name.contains(&term) || description.contains(&term),
+            }
+        })
+    }
+
+    pub fn apply(&self, results: Vec<AurPackage>) -> Vec<AurPackage> {
+        let mut results: Vec<AurPackage> = results.into_iter()
+            .filter(|pkg| !(self.hide_out_of_date && pkg.out_of_date.is_some()))
+            .filter(|pkg| !(self.hide_orphans && pkg.maintainer.is_none()))
+            .filter(|pkg| self.matches_terms(pkg))
+            .collect();
+
+        if let Some(sort) = self.sort {
+            results.sort_by(|a, b| compare(a, b, sort));
+        }
+        if let Some(limit) = self.limit {
+            results.truncate(limit);
+        }
+        results
+    }
+}
+
+fn compare(a: &AurPackage, b: &AurPackage, sort: SortKey) -> Ordering {
+    match sort {
+        SortKey::Votes => b.num_votes.cmp(&a.num_votes),
+        SortKey::Popularity => b.popularity.total_cmp(&a.popularity),
+        SortKey::Modified => b.last_modified.cmp(&a.last_modified),
+        SortKey::Name => a.name.cmp(&b.name),
+    }
+}
+
+/// Parses a yay style selection of numbered results, such as `1 3 5-7 ^4`.
+/// Numbers are 1-based, `a-b` selects a range and a leading `^` excludes a
+/// number or range. A selection made only of exclusions starts from every
+/// result. Returns the selected 0-based indices in ascending order.
+pub fn parse_selection(input: &str, count: usize) -> Result<Vec<usize>, String> {
+    let mut included: Vec<usize> = Vec::new();
+    let mut excluded: Vec<usize> = Vec::new();
+    let mut has_inclusions = false;
+
+    for token in input.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+        let (negate, range) = match token.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_index(start, count)?, parse_index(end, count)?),
+            None => {
+                let index = parse_index(range, count)?;
+                (index, index)
+            }
+        };
+        if start > end {
+            return Err(format!("Invalid range '{}'", token));
+        }
+
+        if negate {
+            excluded.extend(start..=end);
+        } else {
+            has_inclusions = true;
+            included.extend(start..=end);
+        }
+    }
+
+    if !has_inclusions && !excluded.is_empty() {
+        included.extend(0..count);
+    }
+
+    included.sort_unstable();
+    included.dedup();
+    included.retain(|index| !excluded.contains(index));
+    Ok(included)
+}
+
+fn parse_index(number: &str, count: usize) -> Result<usize, String> {
+    let value: usize = number.parse()
+        .map_err(|_| format!("'{}' is not a number", number))?;
+    if value == 0 || value > count {
+        return Err(format!("{} is out of range (1-{})", value, count));
+    }
+    Ok(value - 1)
+}
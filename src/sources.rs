@@ -0,0 +1,111 @@
+use crate::error::{BuildError, build_git_error, build_makepkg_error};
+use crate::network;
+use git2::build::RepoBuilder;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the built-in source backed by `aur_base_url`
+pub const AUR_SOURCE_NAME: &str = "aur";
+
+/// How a package source hands out PKGBUILD repos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// AUR-compatible git server, cloned from `{url}/{pkgbase}.git`
+    Git,
+    /// Local directory holding one `{pkgbase}` directory per PKGBUILD
+    Dir,
+}
+
+/// A place PKGBUILDs are fetched from, configured under `[[sources]]`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PackageSource {
+    pub name: String,
+    pub kind: SourceKind,
+    /// Git base URL, or directory path for `dir` sources
+    pub url: String,
+    /// Sources are tried from the highest priority down. The AUR has priority 0
+    /// and loses ties against configured sources.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl PackageSource {
+    pub fn aur(base_url: &str) -> Self {
+        PackageSource {
+            name: AUR_SOURCE_NAME.to_string(),
+            kind: SourceKind::Git,
+            url: base_url.to_string(),
+            priority: 0,
+        }
+    }
+
+    /// The AUR followed by the configured sources, in the order they are tried
+    pub fn ordered(aur_base_url: &str, extra: &[PackageSource]) -> Vec<PackageSource> {
+        let mut sources = extra.to_vec();
+        sources.push(Self::aur(aur_base_url));
+        // Stable sort, so configured sources keep their order and win ties
+        sources.sort_by_key(|source| std::cmp::Reverse(source.priority));
+        sources
+    }
+
+    pub fn git_url(&self, pkgbase: &str) -> String {
+        format!("{}/{}.git", self.url.trim_end_matches('/'), pkgbase)
+    }
+
+    /// Fetches the PKGBUILD repo of a pkgbase into `dest`. Returns false when this
+    /// source doesn't have it, leaving `dest` untouched.
+    pub fn fetch(&self, pkgbase: &str, dest: &Path) -> Result<bool, BuildError> {
+        match self.kind {
+            SourceKind::Git => {
+                let cloned = RepoBuilder::new()
+                    .fetch_options(network::fetch_options())
+                    .clone(&self.git_url(pkgbase), dest);
+                if let Err(e) = cloned {
+                    if self.name == AUR_SOURCE_NAME {
+                        return Err(build_git_error(format!("Git clone failed: {}", e), pkgbase));
+                    }
+                    // Other servers answer unknown repos with an error
+                    debug!("{} has no {}: {}", self.name, pkgbase, e);
+                    return Ok(false);
+                }
+                // AUR-compatible servers hand out empty repos for unknown packages
+                if !dest.join("PKGBUILD").is_file() {
+                    fs::remove_dir_all(dest).map_err(|e| build_git_error(
+                        format!("Failed to clean up empty clone: {}", e),
+                        pkgbase
+                    ))?;
+                    return Ok(false);
+                }
+                Ok(true)
+            }
+            SourceKind::Dir => {
+                let source_dir = PathBuf::from(&self.url).join(pkgbase);
+                if !source_dir.join("PKGBUILD").is_file() {
+                    return Ok(false);
+                }
+                copy_dir(&source_dir, dest).map_err(|e| build_makepkg_error(
+                    format!("Failed to copy {:?}: {}", source_dir, e),
+                    "fetch"
+                ))?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
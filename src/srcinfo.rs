@@ -0,0 +1,227 @@
+use crate::error::{BuildError, build_makepkg_error};
+use crate::exec::{CommandRunner, Invocation, OutputMode};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A `pkgname` section of a .SRCINFO file
+#[derive(Debug, Clone, Default)]
+pub struct SrcInfoPackage {
+    pub name: String,
+    /// Overrides the pkgbase `depends` when the package section sets it
+    pub depends: Option<Vec<String>>,
+}
+
+/// Parsed contents of a .SRCINFO file
+#[derive(Debug, Clone, Default)]
+pub struct SrcInfo {
+    pub pkgbase: String,
+    pub pkgver: String,
+    pub pkgrel: String,
+    pub epoch: Option<String>,
+    pub depends: Vec<String>,
+    pub makedepends: Vec<String>,
+    pub checkdepends: Vec<String>,
+    pub packages: Vec<SrcInfoPackage>,
+    /// Version constraints of dependencies, such as `>=1.2` for `zlib>=1.2`
+    pub constraints: HashMap<String, String>,
+}
+
+impl SrcInfo {
+    pub fn from_dir(build_dir: &Path) -> Result<Self, BuildError> {
+        let srcinfo_path = build_dir.join(".SRCINFO");
+
+        if !srcinfo_path.exists() {
+            return Err(build_makepkg_error(
+                format!(".SRCINFO file not found at {:?}", srcinfo_path),
+                "dependency extraction"
+            ));
+        }
+
+        let content = fs::read_to_string(&srcinfo_path)
+            .map_err(|e| build_makepkg_error(
+                format!("Failed to read .SRCINFO file: {}", e),
+                "dependency extraction"
+            ))?;
+
+        Ok(Self::parse(&content))
+    }
+
+    /// Reads the .SRCINFO of a local PKGBUILD directory, regenerating it first when
+    /// it is missing or stale. In dry-run mode the regeneration is only printed, so
+    /// an existing .SRCINFO is needed to plan the build.
+    pub fn load_or_generate(build_dir: &Path, runner: &dyn CommandRunner) -> Result<Self, BuildError> {
+        if is_stale(build_dir) {
+            Self::generate(build_dir, runner)?;
+        }
+        if runner.is_dry_run() && !build_dir.join(".SRCINFO").exists() {
+            return Err(build_makepkg_error(
+                format!("No .SRCINFO in {:?} to plan the build from without running makepkg", build_dir),
+                "srcinfo generation"
+            ));
+        }
+        Self::from_dir(build_dir)
+    }
+
+    /// Writes a fresh .SRCINFO with `makepkg --printsrcinfo`
+    pub fn generate(build_dir: &Path, runner: &dyn CommandRunner) -> Result<(), BuildError> {
+        let output = runner.run(&Invocation::new("makepkg")
+            .arg("--printsrcinfo")
+            .current_dir(build_dir)
+            .output(OutputMode::Capture))
+            .map_err(|e| build_makepkg_error(
+                format!("Failed to spawn makepkg: {}", e),
+                "srcinfo generation"
+            ))?;
+
+        if !output.success {
+            return Err(build_makepkg_error(
+                format!("makepkg --printsrcinfo failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()),
+                "srcinfo generation"
+            ));
+        }
+        if runner.is_dry_run() {
+            return Ok(());
+        }
+
+        fs::write(build_dir.join(".SRCINFO"), &output.stdout)
+            .map_err(|e| build_makepkg_error(
+                format!("Failed to write .SRCINFO file: {}", e),
+                "srcinfo generation"
+            ))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut info = SrcInfo::default();
+
+        for line in content.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            let value = value.trim();
+            if matches!(key, "depends" | "makedepends" | "checkdepends")
+                && let (name, Some(constraint)) = split_constraint(value)
+            {
+                info.constraints.insert(name, constraint);
+            }
+
+            if key == "pkgname" {
+                info.packages.push(SrcInfoPackage {
+                    name: value.to_string(),
+                    depends: None,
+                });
+                continue;
+            }
+
+            // Everything after the first pkgname belongs to that package section
+            if let Some(package) = info.packages.last_mut() {
+                if key == "depends" && !value.is_empty() {
+                    package.depends.get_or_insert_with(Vec::new).push(strip_constraint(value));
+                } else if key == "depends" {
+                    package.depends = Some(Vec::new());
+                }
+                continue;
+            }
+
+            match key {
+                "pkgbase" => info.pkgbase = value.to_string(),
+                "pkgver" => info.pkgver = value.to_string(),
+                "pkgrel" => info.pkgrel = value.to_string(),
+                "epoch" => info.epoch = Some(value.to_string()),
+                "depends" => push_unique(&mut info.depends, value),
+                "makedepends" => push_unique(&mut info.makedepends, value),
+                "checkdepends" => push_unique(&mut info.checkdepends, value),
+                _ => {}
+            }
+        }
+
+        info
+    }
+
+    /// Full version string as used in package file names (`[epoch:]pkgver-pkgrel`)
+    pub fn version(&self) -> String {
+        match &self.epoch {
+            Some(epoch) => format!("{}:{}-{}", epoch, self.pkgver, self.pkgrel),
+            None => format!("{}-{}", self.pkgver, self.pkgrel),
+        }
+    }
+
+    pub fn pkgnames(&self) -> Vec<String> {
+        self.packages.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// Runtime dependencies of a single package in this pkgbase
+    pub fn package_depends(&self, pkgname: &str) -> Vec<String> {
+        self.packages.iter()
+            .find(|p| p.name == pkgname)
+            .and_then(|p| p.depends.clone())
+            .unwrap_or_else(|| self.depends.clone())
+    }
+
+    /// Everything needed to build the pkgbase and run the given packages
+    pub fn dependencies_for(&self, pkgnames: &[String]) -> Vec<String> {
+        let mut dependencies = self.depends.clone();
+        for pkgname in pkgnames {
+            for dep in self.package_depends(pkgname) {
+                push_unique(&mut dependencies, &dep);
+            }
+        }
+        for dep in self.makedepends.iter().chain(&self.checkdepends) {
+            push_unique(&mut dependencies, dep);
+        }
+        dependencies
+    }
+
+    /// A dependency with its version constraint put back, as written in the PKGBUILD
+    pub fn constrained(&self, dep: &str) -> String {
+        match self.constraints.get(dep) {
+            Some(constraint) => format!("{}{}", dep, constraint),
+            None => dep.to_string(),
+        }
+    }
+
+    /// Every dependency of the pkgbase, across all of its packages
+    pub fn all_dependencies(&self) -> Vec<String> {
+        self.dependencies_for(&self.pkgnames())
+    }
+}
+
+/// Whether the .SRCINFO in a PKGBUILD directory is missing or older than the PKGBUILD
+pub fn is_stale(build_dir: &Path) -> bool {
+    let modified = |name: &str| fs::metadata(build_dir.join(name)).and_then(|m| m.modified()).ok();
+    match (modified(".SRCINFO"), modified("PKGBUILD")) {
+        (Some(srcinfo), Some(pkgbuild)) => srcinfo < pkgbuild,
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+/// Strips a version constraint from a dependency (`foo>=1.0` becomes `foo`)
+pub fn strip_constraint(dep: &str) -> String {
+    dep.split(&['<', '>', '=', ' '][..])
+        .next()
+        .unwrap_or(dep)
+        .trim()
+        .to_string()
+}
+
+/// Splits a dependency into its name and version constraint (`foo>=1.0` becomes
+/// `foo` and `>=1.0`)
+pub fn split_constraint(dep: &str) -> (String, Option<String>) {
+    let name = strip_constraint(dep);
+    let constraint = dep.trim()[name.len()..].trim();
+    if constraint.is_empty() || constraint.starts_with(':') {
+        (name, None)
+    } else {
+        (name, Some(constraint.to_string()))
+    }
+}
+
+fn push_unique(list: &mut Vec<String>, dep: &str) {
+    let pkg_name = strip_constraint(dep);
+    if !pkg_name.is_empty() && !list.contains(&pkg_name) {
+        list.push(pkg_name);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::alpm::AlpmWrapper;
+use crate::aur::AurBackend;
+use crate::build::PackageBuilder;
+use crate::config::AppConfig;
+use crate::error::AurError;
+use crate::srcinfo::{SrcInfo, split_constraint};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Where a node of a dependency tree is satisfied from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeSource {
+    Installed,
+    Repo,
+    Cached,
+    Aur,
+    Missing,
+}
+
+impl NodeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NodeSource::Installed => "installed",
+            NodeSource::Repo => "repo",
+            NodeSource::Cached => "cached",
+            NodeSource::Aur => "aur",
+            NodeSource::Missing => "missing",
+        }
+    }
+}
+
+/// A dependency looked up once, however many times it appears in the tree
+#[derive(Debug, Clone)]
+pub struct ResolvedPackage {
+    pub source: NodeSource,
+    /// Package satisfying the dependency when it is a provider with another name
+    pub provider: Option<String>,
+    /// Sync database name for repo packages
+    pub repo: Option<String>,
+    /// Dependencies, or dependents in reverse mode, with their constraints
+    pub depends: Vec<String>,
+}
+
+/// A node of a dependency tree
+#[derive(Debug, Clone, Serialize)]
+pub struct DepNode {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+    pub source: NodeSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Already expanded earlier in the tree, so its children are left out
+    pub repeated: bool,
+    pub children: Vec<DepNode>,
+}
+
+/// Looks up the root and everything it depends on, breadth first. In reverse mode
+/// the root must be installed and the installed packages requiring it are followed.
+pub async fn resolve(
+    root: &str,
+    reverse: bool,
+    max_depth: Option<usize>,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+    config: &AppConfig,
+) -> anyhow::Result<HashMap<String, ResolvedPackage>> {
+    let cache_dir = config.cache_path()?;
+    let mut resolved: HashMap<String, ResolvedPackage> = HashMap::new();
+    let mut queue: VecDeque<(String, usize)> = VecDeque::from([(root.to_string(), 0)]);
+
+    while let Some((dep, depth)) = queue.pop_front() {
+        let (name, constraint) = split_constraint(&dep);
+        if resolved.contains_key(&name) {
+            continue;
+        }
+
+        let package = if reverse {
+            ResolvedPackage {
+                source: NodeSource::Installed,
+                provider: None,
+                repo: None,
+                depends: alpm.required_by(&name)?,
+            }
+        } else {
+            resolve_one(&name, constraint.as_deref(), aur, alpm, &cache_dir).await?
+        };
+
+        if max_depth.is_none_or(|max| depth < max) {
+            for child in &package.depends {
+                queue.push_back((child.clone(), depth + 1));
+            }
+        }
+        resolved.insert(name, package);
+    }
+
+    Ok(resolved)
+}
+
+/// Installed packages win over repo packages, which win over cached and AUR builds
+async fn resolve_one(
+    name: &str,
+    constraint: Option<&str>,
+    aur: &dyn AurBackend,
+    alpm: &AlpmWrapper,
+    cache_dir: &std::path::Path,
+) -> anyhow::Result<ResolvedPackage> {
+    let dep = format!("{}{}", name, constraint.unwrap_or_default());
+    let provider = |found: &str| (found != name).then(|| found.to_string());
+
+    if let Some((found, depends)) = alpm.installed_satisfier(&dep) {
+        return Ok(ResolvedPackage { source: NodeSource::Installed, provider: provider(&found), repo: None, depends });
+    }
+    if let Some((repo, pkg)) = alpm.repo_satisfier(&dep) {
+        return Ok(ResolvedPackage { source: NodeSource::Repo, provider: provider(&pkg.name), repo: Some(repo), depends: pkg.depends });
+    }
+    if PackageBuilder::find_cached_package(cache_dir, name).is_some() {
+        let depends = PackageBuilder::read_dependency_list(name, cache_dir).unwrap_or_default();
+        return Ok(ResolvedPackage { source: NodeSource::Cached, provider: None, repo: None, depends });
+    }
+
+    match aur.get_package_info(name).await {
+        Ok(info) => {
+            let pkgbase = info.package_base.unwrap_or_else(|| name.to_string());
+            let srcinfo = SrcInfo::parse(&aur.get_repo_file(&pkgbase, ".SRCINFO").await?);
+            let depends = srcinfo.dependencies_for(&[name.to_string()]).iter()
+                .map(|dep| srcinfo.constrained(dep))
+                .collect();
+            Ok(ResolvedPackage { source: NodeSource::Aur, provider: None, repo: None, depends })
+        }
+        Err(AurError::NotFound(_)) => {
+            Ok(ResolvedPackage { source: NodeSource::Missing, provider: None, repo: None, depends: Vec::new() })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Builds the tree from resolved packages. Each package is expanded the first time
+/// it appears, later appearances are marked as repeated.
+pub fn build_tree(root: &str, resolved: &HashMap<String, ResolvedPackage>) -> DepNode {
+    let mut expanded = HashSet::new();
+    build_node(root, resolved, &mut expanded)
+}
+
+fn build_node(dep: &str, resolved: &HashMap<String, ResolvedPackage>, expanded: &mut HashSet<String>) -> DepNode {
+    let (name, constraint) = split_constraint(dep);
+    let package = resolved.get(&name);
+    let repeated = !expanded.insert(name.clone());
+
+    let children = match package {
+        // Children beyond the depth limit were never resolved
+        Some(package) if !repeated => package.depends.iter()
+            .filter(|child| resolved.contains_key(&split_constraint(child).0))
+            .map(|child| build_node(child, resolved, expanded))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    DepNode {
+        source: package.map_or(NodeSource::Missing, |p| p.source),
+        provider: package.and_then(|p| p.provider.clone()),
+        repo: package.and_then(|p| p.repo.clone()),
+        name,
+        constraint,
+        repeated,
+        children,
+    }
+}
+
+/// Renders the tree as a Graphviz digraph, with one node per package
+pub fn to_dot(root: &DepNode) -> String {
+    let mut nodes: Vec<String> = Vec::new();
+    let mut edges: Vec<String> = Vec::new();
+    collect_dot(root, &mut nodes, &mut edges);
+
+    let mut dot = String::from("digraph dependencies {\n    rankdir=LR;\n");
+    for line in nodes.iter().chain(&edges) {
+        dot.push_str("    ");
+        dot.push_str(line);
+        dot.push('\n');
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn collect_dot(node: &DepNode, nodes: &mut Vec<String>, edges: &mut Vec<String>) {
+    if !node.repeated {
+        nodes.push(format!(
+            "\"{}\" [label=\"{}\\n({})\"];",
+            node.name, node.name, node.source.as_str()
+        ));
+    }
+    for child in &node.children {
+        let label = match &child.constraint {
+            Some(constraint) => format!(" [label=\"{}\"]", constraint),
+            None => String::new(),
+        };
+        let edge = format!("\"{}\" -> \"{}\"{};", node.name, child.name, label);
+        if !edges.contains(&edge) {
+            edges.push(edge);
+        }
+        collect_dot(child, nodes, edges);
+    }
+}
+
+/// How a package in a `why` chain depends on the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkKind {
+    /// Listed in the local database's required-by data
+    RequiredBy,
+    /// Listed in a `.lilac_deps` record, such as a make dependency
+    LilacRecord,
+}
+
+/// A step of a chain from a package back to an explicitly installed one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WhyLink {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via: Option<LinkKind>,
+}
+
+/// Shortest chains from `target` back to explicitly installed packages, one per
+/// explicit package reached. An explicit target is its own single-link chain, and
+/// an orphan has no chains.
+pub fn why_chains(
+    target: &str,
+    mut dependents: impl FnMut(&str) -> Vec<(String, LinkKind)>,
+    mut is_explicit: impl FnMut(&str) -> bool,
+) -> Vec<Vec<WhyLink>> {
+    let root = vec![WhyLink { name: target.to_string(), via: None }];
+    if is_explicit(target) {
+        return vec![root];
+    }
+
+    let mut chains = Vec::new();
+    let mut visited: HashSet<String> = HashSet::from([target.to_string()]);
+    let mut queue: VecDeque<Vec<WhyLink>> = VecDeque::from([root]);
+    while let Some(chain) = queue.pop_front() {
+        let last = &chain[chain.len() - 1].name;
+        for (name, kind) in dependents(last) {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let explicit = is_explicit(&name);
+            let mut next = chain.clone();
+            next.push(WhyLink { name, via: Some(kind) });
+            if explicit {
+                chains.push(next);
+            } else {
+                queue.push_back(next);
+            }
+        }
+    }
+    chains
+}
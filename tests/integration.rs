@@ -4,7 +4,7 @@
 mod tests {
     use lilac_aur::error::*;
     use lilac_aur::config::AppConfig;
-    use tempfile;
+
     use assert_cmd::Command;
     use predicates::prelude::*;
 
@@ -18,6 +18,10 @@ mod tests {
         assert!(format!("{}", e).contains("Package not found in AUR"));
         let e = AurError::ApiError("fail".into());
         assert!(format!("{}", e).contains("AUR API error"));
+        let e = AurError::RpcError("Too many package results.".into());
+        assert!(format!("{}", e).contains("AUR RPC error"));
+        let e = AurError::RateLimited("retry after 60s".into());
+        assert!(format!("{}", e).contains("Rate limited"));
     }
 
     #[test]
@@ -92,12 +96,14 @@ mod tests {
         use lilac_aur::aur::AurPackage;
         let pkg = AurPackage {
             name: "foo".into(),
+            package_base: Some("foo".into()),
             version: "1.0".into(),
             description: Some("desc".into()),
             url: Some("http://foo".into()),
             maintainer: Some("me".into()),
             num_votes: 1,
             popularity: 0.1,
+            out_of_date: None,
             first_submitted: 0,
             last_modified: 0,
         };
@@ -114,4 +120,1313 @@ mod tests {
             .success()
             .stdout(predicate::str::contains("Usage: lilac"));
     }
-} 
+
+    #[test]
+    fn test_offline_sync_meta_is_refused_before_opening_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        Command::cargo_bin("lilac")
+            .unwrap()
+            .args(["--offline", "sync-meta", "--cache-dir"])
+            .arg(dir.path())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("can't run with --offline"));
+    }
+
+    #[test]
+    fn test_global_flags_before_subcommand() {
+        let home = tempfile::tempdir().unwrap();
+        Command::cargo_bin("lilac")
+            .unwrap()
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path().join(".config"))
+            .args(["--set", "build.jobs=3", "config", "get", "build.jobs"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("3"));
+
+        // A subcommand name after bare terms is just another search term
+        Command::cargo_bin("lilac")
+            .unwrap()
+            .args(["foo", "install", "bar", "--help"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Usage: lilac [OPTIONS] [TERMS]..."));
+    }
+
+    #[test]
+    fn test_dedup_targets_keeps_order() {
+        use lilac_aur::commands::dedup_targets;
+        let targets: Vec<String> = ["foo", "bar", "foo", "baz", "bar"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(dedup_targets(&targets), vec!["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn test_install_requires_targets() {
+        Command::cargo_bin("lilac")
+            .unwrap()
+            .arg("install")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("<PACKAGES>"));
+    }
+
+    #[test]
+    fn test_srcinfo_split_package() {
+        use lilac_aur::srcinfo::SrcInfo;
+        let content = "pkgbase = foo-base
+	pkgver = 1.2.3
+	pkgrel = 2
+	makedepends = cmake
+	depends = glibc
+	depends = zlib>=1.2
+
+pkgname = foo
+
+pkgname = foo-docs
+	depends = foo
+";
+        let info = SrcInfo::parse(content);
+        assert_eq!(info.pkgbase, "foo-base");
+        assert_eq!(info.version(), "1.2.3-2");
+        assert_eq!(info.pkgnames(), vec!["foo", "foo-docs"]);
+        assert_eq!(info.package_depends("foo"), vec!["glibc", "zlib"]);
+        assert_eq!(info.package_depends("foo-docs"), vec!["foo"]);
+        assert_eq!(info.dependencies_for(&["foo".to_string()]), vec!["glibc", "zlib", "cmake"]);
+    }
+
+    #[test]
+    fn test_parse_package_filename() {
+        use lilac_aur::build::PackageBuilder;
+        let parsed = PackageBuilder::parse_package_filename("python-3to2-1:1.1.1-4-any.pkg.tar.zst");
+        assert_eq!(parsed, Some(("python-3to2".into(), "1:1.1.1-4".into(), "any".into())));
+        assert!(PackageBuilder::parse_package_filename("foo.lilac_deps").is_none());
+    }
+
+    #[test]
+    fn test_packagebuilder_find_cached_package_exact_name() {
+        use lilac_aur::build::PackageBuilder;
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("foo-docs-1.0-1-any.pkg.tar.zst"), "").unwrap();
+        assert!(PackageBuilder::find_cached_package(temp.path(), "foo").is_none());
+        std::fs::write(temp.path().join("foo-1.0-1-x86_64.pkg.tar.zst"), "").unwrap();
+        let found = PackageBuilder::find_cached_package(temp.path(), "foo").unwrap();
+        assert!(found.ends_with("foo-1.0-1-x86_64.pkg.tar.zst"));
+    }
+
+    #[test]
+    fn test_list_cached_packages_groups_debug() {
+        use lilac_aur::build::{CachedPackage, PackageBuilder};
+        let temp = tempfile::tempdir().unwrap();
+        for file in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-debug-1.0-1-x86_64.pkg.tar.zst",
+            "bar-debug-2.0-1-x86_64.pkg.tar.zst",
+            "foo.lilac_deps",
+        ] {
+            std::fs::write(temp.path().join(file), "").unwrap();
+        }
+
+        let packages = PackageBuilder::list_cached_packages(temp.path()).unwrap();
+        assert_eq!(packages, vec![
+            CachedPackage { name: "bar-debug".into(), pkgbase: "bar-debug".into(), version: "2.0-1".into(), debug_version: None },
+            CachedPackage { name: "foo".into(), pkgbase: "foo".into(), version: "1.0-1".into(), debug_version: Some("1.0-1".into()) },
+        ]);
+    }
+
+    #[test]
+    fn test_list_cached_packages_groups_split_debug_by_pkgbase() {
+        use lilac_aur::build::PackageBuilder;
+        let temp = tempfile::tempdir().unwrap();
+        for file in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-docs-1.0-1-any.pkg.tar.zst",
+            "foo-base-debug-1.0-1-x86_64.pkg.tar.zst",
+        ] {
+            std::fs::write(temp.path().join(file), "").unwrap();
+        }
+        std::fs::write(temp.path().join("foo.lilac_pkgbase"), "foo-base").unwrap();
+        std::fs::write(temp.path().join("foo-docs.lilac_pkgbase"), "foo-base").unwrap();
+
+        assert_eq!(PackageBuilder::cached_pkgbase(temp.path(), "foo-docs"), "foo-base");
+        assert_eq!(PackageBuilder::cached_pkgbase(temp.path(), "bar"), "bar");
+        assert_eq!(PackageBuilder::cached_pkgbase_members(temp.path(), "foo-base"), vec!["foo", "foo-docs"]);
+
+        let packages = PackageBuilder::list_cached_packages(temp.path()).unwrap();
+        let listed: Vec<(&str, Option<&str>)> = packages.iter()
+            .map(|pkg| (pkg.name.as_str(), pkg.debug_version.as_deref()))
+            .collect();
+        assert_eq!(listed, vec![("foo", Some("1.0-1")), ("foo-docs", None)]);
+        assert!(packages.iter().all(|pkg| pkg.pkgbase == "foo-base"));
+    }
+
+    fn aur_package(name: &str, version: &str) -> lilac_aur::aur::AurPackage {
+        lilac_aur::aur::AurPackage {
+            name: name.into(),
+            package_base: None,
+            version: version.into(),
+            description: None,
+            url: None,
+            maintainer: None,
+            num_votes: 0,
+            popularity: 0.0,
+            out_of_date: None,
+            first_submitted: 0,
+            last_modified: 0,
+        }
+    }
+
+    #[test]
+    fn test_find_outdated() {
+        use lilac_aur::commands::find_outdated;
+        let cached = vec![
+            ("foo".to_string(), "1.0-1".to_string()),
+            ("bar".to_string(), "2.0-1".to_string()),
+            ("gone".to_string(), "1.0-1".to_string()),
+        ];
+        let latest = vec![aur_package("foo", "1.1-1"), aur_package("bar", "2.0-1")];
+        let outdated = find_outdated(&cached, &latest);
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].name, "foo");
+        assert_eq!(outdated[0].latest_version, "1.1-1");
+    }
+
+    #[test]
+    fn test_package_info_json_output() {
+        use lilac_aur::output::PackageInfo;
+        let pkg = aur_package("foo", "1.0-1");
+        let info = PackageInfo {
+            source: "aur",
+            installed_version: Some("0.9-1".into()),
+            package: &pkg,
+            dependencies: Some(vec!["bar".into()]),
+        };
+        let json: serde_json::Value = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["name"], "foo");
+        assert_eq!(json["source"], "aur");
+        assert_eq!(json["installed_version"], "0.9-1");
+        assert_eq!(json["version"], "1.0-1");
+        assert_eq!(json["dependencies"][0], "bar");
+        assert!(!json.to_string().contains('\u{1b}'));
+    }
+
+    #[test]
+    fn test_search_filter_terms_sort_and_limit() {
+        use lilac_aur::aur::SearchField;
+        use lilac_aur::search::{SearchFilter, SortKey};
+        let mut a = aur_package("foo-git", "1.0-1");
+        a.description = Some("a fast tool".into());
+        a.num_votes = 5;
+        let mut b = aur_package("foo-bin", "1.0-1");
+        b.description = Some("a fast tool, prebuilt".into());
+        b.num_votes = 50;
+        let mut c = aur_package("fastbar", "1.0-1");
+        c.description = Some("unrelated".into());
+        c.maintainer = Some("me".into());
+        let mut d = aur_package("foo-orphan", "1.0-1");
+        d.description = Some("fast".into());
+        d.maintainer = None;
+        a.maintainer = Some("me".into());
+        b.maintainer = Some("me".into());
+
+        let filter = SearchFilter {
+            terms: vec!["fast".into(), "foo".into()],
+            by: SearchField::NameDesc,
+            sort: Some(SortKey::Votes),
+            limit: Some(5),
+            hide_out_of_date: false,
+            hide_orphans: true,
+        };
+        assert_eq!(filter.rpc_term(), Some("fast"));
+        let results = filter.apply(vec![a, b, c, d]);
+        let names: Vec<&str> = results.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["foo-bin", "foo-git"]);
+    }
+
+    #[test]
+    fn test_search_filter_matches_selected_field() {
+        use lilac_aur::aur::SearchField;
+        use lilac_aur::search::SearchFilter;
+        let mut a = aur_package("tool", "1.0-1");
+        a.description = Some("made by bob".into());
+        a.maintainer = Some("Alice".into());
+        let mut b = aur_package("bob-tool", "1.0-1");
+        b.maintainer = Some("carol".into());
+
+        // Only the name is matched with --by name
+        let filter = SearchFilter { terms: vec!["tool".into(), "bob".into()], ..Default::default() };
+        let names: Vec<String> = filter.apply(vec![a.clone(), b.clone()]).into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["bob-tool"]);
+
+        let filter = SearchFilter {
+            terms: vec!["alice".into(), "alice".into()],
+            by: SearchField::Maintainer,
+            ..Default::default()
+        };
+        let names: Vec<String> = filter.apply(vec![a, b]).into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["tool"]);
+
+        let filter = SearchFilter { terms: vec!["zlib".into(), "cmake".into()], by: SearchField::Depends, ..Default::default() };
+        assert_eq!(filter.check(), Err("Searching by depends takes a single term".to_string()));
+        let filter = SearchFilter { terms: vec!["zlib".into()], by: SearchField::Depends, ..Default::default() };
+        assert_eq!(filter.check(), Ok(()));
+    }
+
+    #[test]
+    fn test_search_result_json_marks_source() {
+        use lilac_aur::search::{FoundPackage, SearchResult};
+        let result = SearchResult {
+            source: "aur".into(),
+            installed_version: None,
+            package: FoundPackage::Aur(aur_package("foo", "1.0-1")),
+        };
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["source"], "aur");
+        assert_eq!(json["name"], "foo");
+        assert!(json["installed_version"].is_null());
+    }
+
+    #[test]
+    fn test_srcinfo_staleness() {
+        use lilac_aur::srcinfo::is_stale;
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("PKGBUILD"), "pkgname=foo\n").unwrap();
+        assert!(is_stale(dir.path()));
+
+        let now = SystemTime::now();
+        let srcinfo = std::fs::File::create(dir.path().join(".SRCINFO")).unwrap();
+        srcinfo.set_modified(now).unwrap();
+        let pkgbuild = std::fs::File::options().write(true).open(dir.path().join("PKGBUILD")).unwrap();
+        pkgbuild.set_modified(now - Duration::from_secs(60)).unwrap();
+        assert!(!is_stale(dir.path()));
+
+        pkgbuild.set_modified(now + Duration::from_secs(60)).unwrap();
+        assert!(is_stale(dir.path()));
+    }
+
+    #[test]
+    fn test_package_sources_order_and_git_url() {
+        use lilac_aur::sources::{PackageSource, SourceKind};
+        let source = |name: &str, priority| PackageSource {
+            name: name.into(),
+            kind: SourceKind::Git,
+            url: format!("https://{}.example.com/", name),
+            priority,
+        };
+        let sources = PackageSource::ordered(
+            "https://aur.archlinux.org",
+            &[source("low", -1), source("tie", 0), source("high", 10)],
+        );
+        let names: Vec<&str> = sources.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["high", "tie", "aur", "low"]);
+        assert_eq!(sources[2].git_url("foo"), "https://aur.archlinux.org/foo.git");
+        assert_eq!(sources[0].git_url("foo"), "https://high.example.com/foo.git");
+    }
+
+    #[test]
+    fn test_dir_source_fetch() {
+        use lilac_aur::sources::{PackageSource, SourceKind};
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("foo/patches")).unwrap();
+        std::fs::write(root.path().join("foo/PKGBUILD"), "pkgname=foo\n").unwrap();
+        std::fs::write(root.path().join("foo/patches/fix.patch"), "").unwrap();
+        let source = PackageSource {
+            name: "local".into(),
+            kind: SourceKind::Dir,
+            url: root.path().to_string_lossy().into_owned(),
+            priority: 0,
+        };
+
+        let dest = tempfile::tempdir().unwrap();
+        assert!(source.fetch("foo", &dest.path().join("foo")).unwrap());
+        assert!(dest.path().join("foo/PKGBUILD").is_file());
+        assert!(dest.path().join("foo/patches/fix.patch").is_file());
+        assert!(!source.fetch("bar", &dest.path().join("bar")).unwrap());
+        assert!(!dest.path().join("bar").exists());
+    }
+
+    #[test]
+    fn test_clone_repo_falls_through_failing_source() {
+        use lilac_aur::SnapshotAur;
+        use lilac_aur::build::{BuildSummary, BuiltPackage, PackageBuilder};
+        use std::io::Write;
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("pkgbuilds/foo")).unwrap();
+        std::fs::write(root.path().join("pkgbuilds/foo/PKGBUILD"), "pkgname=foo\n").unwrap();
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(r#"
+[[sources]]
+name = "mirror"
+kind = "dir"
+url = "{}"
+priority = -1
+"#, root.path().join("pkgbuilds").display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        // The snapshot backend can't clone, which used to stop the lookup at the AUR
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"[]").unwrap();
+        let snapshot_path = root.path().join("meta.json.gz");
+        std::fs::write(&snapshot_path, encoder.finish().unwrap()).unwrap();
+        let aur = SnapshotAur::open(&snapshot_path).unwrap();
+
+        let dest = root.path().join("build/foo");
+        assert_eq!(PackageBuilder::clone_repo("foo", &dest, &aur, &config).unwrap(), "mirror");
+        assert!(dest.join("PKGBUILD").is_file());
+
+        let err = PackageBuilder::clone_repo("bar", &root.path().join("build/bar"), &aur, &config).unwrap_err();
+        assert!(err.to_string().contains("offline mode"), "{}", err);
+
+        let summary = BuildSummary {
+            targets: vec![BuiltPackage {
+                name: "foo".into(),
+                pkgbase: "foo".into(),
+                source: "mirror".into(),
+                path: dest.join("foo-1-1-any.pkg.tar.zst"),
+            }],
+            dependencies: Vec::new(),
+        };
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["targets"][0]["source"], "mirror");
+        assert_eq!(summary.target_paths(), vec![dest.join("foo-1-1-any.pkg.tar.zst")]);
+    }
+
+    #[test]
+    fn test_update_repo_fast_forwards_clone() {
+        use git2::{Repository, Signature};
+        use lilac_aur::build::PackageBuilder;
+
+        fn commit_file(repo: &Repository, name: &str, content: &str) {
+            std::fs::write(repo.workdir().unwrap().join(name), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new(name)).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::now("test", "test@example.com").unwrap();
+            let parents: Vec<git2::Commit> = repo.head().ok()
+                .map(|head| vec![head.peel_to_commit().unwrap()])
+                .unwrap_or_default();
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            repo.commit(Some("HEAD"), &sig, &sig, "update", &tree, &parents).unwrap();
+        }
+
+        let root = tempfile::tempdir().unwrap();
+        let origin = Repository::init(root.path().join("origin")).unwrap();
+        commit_file(&origin, "PKGBUILD", "pkgver=1\n");
+
+        let clone_path = root.path().join("foo");
+        Repository::clone(root.path().join("origin").to_str().unwrap(), &clone_path).unwrap();
+        assert!(!PackageBuilder::update_repo("foo", &clone_path).unwrap());
+
+        commit_file(&origin, "PKGBUILD", "pkgver=2\n");
+        assert!(PackageBuilder::update_repo("foo", &clone_path).unwrap());
+        assert_eq!(std::fs::read_to_string(clone_path.join("PKGBUILD")).unwrap(), "pkgver=2\n");
+    }
+
+    #[test]
+    fn test_fetch_repo_files_lists_tree() {
+        use git2::{Repository, Signature};
+        use lilac_aur::build::PackageBuilder;
+
+        let root = tempfile::tempdir().unwrap();
+        let origin = Repository::init(root.path()).unwrap();
+        std::fs::create_dir(root.path().join("patches")).unwrap();
+        std::fs::write(root.path().join("PKGBUILD"), "pkgname=foo\n").unwrap();
+        std::fs::write(root.path().join("patches/fix.patch"), "diff\n").unwrap();
+        let mut index = origin.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        let tree = origin.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        origin.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[]).unwrap();
+
+        let url = format!("file://{}", root.path().display());
+        let files = PackageBuilder::fetch_repo_files("foo", &url).unwrap();
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["PKGBUILD", "patches/fix.patch"]);
+        assert_eq!(files[0].1, b"pkgname=foo\n");
+    }
+
+    #[test]
+    fn test_srcinfo_keeps_constraints() {
+        use lilac_aur::srcinfo::{SrcInfo, split_constraint};
+        let info = SrcInfo::parse("pkgbase = foo\n\tdepends = zlib>=1.2\n\tdepends = glibc\npkgname = foo\n");
+        assert_eq!(info.constrained("zlib"), "zlib>=1.2");
+        assert_eq!(info.constrained("glibc"), "glibc");
+        assert_eq!(split_constraint("python<3.13"), ("python".to_string(), Some("<3.13".to_string())));
+        assert_eq!(split_constraint("bash"), ("bash".to_string(), None));
+    }
+
+    #[test]
+    fn test_dependency_tree_and_dot() {
+        use lilac_aur::tree::{NodeSource, ResolvedPackage, build_tree, to_dot};
+        use std::collections::HashMap;
+
+        let package = |source, depends: &[&str]| ResolvedPackage {
+            source,
+            provider: None,
+            repo: None,
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+        };
+        let mut resolved = HashMap::new();
+        resolved.insert("app".to_string(), package(NodeSource::Aur, &["libfoo>=2", "glibc"]));
+        resolved.insert("libfoo".to_string(), package(NodeSource::Cached, &["glibc"]));
+        resolved.insert("glibc".to_string(), package(NodeSource::Installed, &[]));
+
+        let root = build_tree("app", &resolved);
+        assert_eq!(root.children.len(), 2);
+        let libfoo = &root.children[0];
+        assert_eq!(libfoo.constraint.as_deref(), Some(">=2"));
+        assert_eq!(libfoo.source, NodeSource::Cached);
+        assert!(!libfoo.children[0].repeated);
+        assert!(root.children[1].repeated);
+
+        let dot = to_dot(&root);
+        assert!(dot.starts_with("digraph dependencies {"));
+        assert!(dot.contains("\"app\" -> \"libfoo\" [label=\">=2\"];"));
+        assert!(dot.contains("\"glibc\" [label=\"glibc\\n(installed)\"];"));
+        assert_eq!(dot.matches("\"glibc\" [").count(), 1);
+    }
+
+    #[test]
+    fn test_recorded_dependents() {
+        use lilac_aur::build::PackageBuilder;
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(cache.path().join("app.lilac_deps"), "libfoo\nglibc\n").unwrap();
+        std::fs::write(cache.path().join("tool.lilac_deps"), "libfoo-extra\n").unwrap();
+        std::fs::write(cache.path().join("other.lilac_deps"), "libfoo\n").unwrap();
+        assert_eq!(PackageBuilder::recorded_dependents(cache.path(), "libfoo"), vec!["app", "other"]);
+        assert!(PackageBuilder::recorded_dependents(cache.path(), "missing").is_empty());
+    }
+
+    #[test]
+    fn test_why_chains_reach_explicit_packages() {
+        use lilac_aur::tree::{LinkKind, why_chains};
+        let dependents = |name: &str| match name {
+            "zlib" => vec![("libfoo".to_string(), LinkKind::RequiredBy), ("cmake".to_string(), LinkKind::RequiredBy)],
+            "libfoo" => vec![("app".to_string(), LinkKind::LilacRecord)],
+            "cmake" => vec![("app".to_string(), LinkKind::RequiredBy)],
+            _ => vec![],
+        };
+        let explicit = |name: &str| name == "app";
+
+        let chains = why_chains("zlib", dependents, explicit);
+        assert_eq!(chains.len(), 1);
+        let names: Vec<&str> = chains[0].iter().map(|link| link.name.as_str()).collect();
+        assert_eq!(names, vec!["zlib", "libfoo", "app"]);
+        assert_eq!(chains[0][2].via, Some(LinkKind::LilacRecord));
+
+        assert_eq!(why_chains("app", dependents, explicit).len(), 1);
+        assert!(why_chains("orphan", dependents, explicit).is_empty());
+    }
+
+    #[test]
+    fn test_plan_removal_keeps_shared_dependencies() {
+        use lilac_aur::commands::plan_removal;
+        let recorded = |name: &str| -> Vec<String> {
+            match name {
+                "app" => vec!["libfoo".into(), "libbar".into(), "cmake".into(), "gone".into()],
+                "libfoo" => vec!["libbaz".into()],
+                "libbar" => vec!["libqux".into()],
+                _ => vec![],
+            }
+        };
+        let dependents = |name: &str| -> Vec<String> {
+            match name {
+                "libfoo" => vec!["app".into()],
+                "libbaz" => vec!["libfoo".into()],
+                "libbar" => vec!["app".into(), "other".into()],
+                "libqux" => vec!["libbar".into()],
+                _ => vec![],
+            }
+        };
+        let plan = plan_removal(
+            &["app".to_string()],
+            recorded,
+            dependents,
+            |name| name != "gone",
+            |name| name == "cmake",
+        );
+        assert_eq!(plan.remove, vec!["app", "libfoo", "libbaz"]);
+        let kept: Vec<&str> = plan.kept.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(kept, vec!["cmake", "libbar", "libqux"]);
+        assert!(plan.kept[0].explicit);
+        assert_eq!(plan.kept[1].needed_by, vec!["other"]);
+        assert_eq!(plan.kept[2].needed_by, vec!["libbar"]);
+    }
+
+    #[test]
+    fn test_cascade_targets_follow_dependents() {
+        use lilac_aur::commands::{cascade_targets, plan_removal};
+        let required_by = |name: &str| -> Vec<String> {
+            match name {
+                "libfoo" => vec!["app".into(), "tool".into()],
+                "app" => vec!["app-plugin".into()],
+                "app-plugin" => vec!["app".into()],
+                _ => vec![],
+            }
+        };
+        let targets = cascade_targets(&["libfoo".to_string()], required_by);
+        assert_eq!(targets, vec!["libfoo", "app", "tool", "app-plugin"]);
+
+        // Dependents removed by the cascade no longer keep recorded dependencies
+        let recorded = |name: &str| -> Vec<String> {
+            if name == "libfoo" { vec!["zlib-ng".into()] } else { vec![] }
+        };
+        let dependents = |name: &str| -> Vec<String> {
+            if name == "zlib-ng" { vec!["libfoo".into(), "tool".into()] } else { vec![] }
+        };
+        let plan = plan_removal(&targets, recorded, dependents, |_| true, |_| false);
+        assert_eq!(plan.remove, vec!["libfoo", "app", "tool", "app-plugin", "zlib-ng"]);
+        assert!(plan.kept.is_empty());
+    }
+
+    #[test]
+    fn test_remove_options_pacman_flag() {
+        use lilac_aur::alpm::RemoveOptions;
+        assert_eq!(RemoveOptions::default().pacman_flag(), "-Rs");
+        assert_eq!(RemoveOptions { cascade: true, nosave: true }.pacman_flag(), "-Rscn");
+    }
+
+    #[test]
+    fn test_system_runner_dry_run_runs_nothing() {
+        use lilac_aur::alpm::PacmanOptions;
+        use lilac_aur::exec::{CommandRunner, Invocation, OutputMode, SystemRunner};
+        let invocation = PacmanOptions::default().invocation().arg("-U").args(["/tmp/foo-1.0-1-x86_64.pkg.tar.zst"]);
+        assert_eq!(invocation.to_string(), "sudo pacman -U /tmp/foo-1.0-1-x86_64.pkg.tar.zst");
+
+        let missing = Invocation::new("lilac-test-no-such-program");
+        assert!(SystemRunner::new(true).run(&missing).unwrap().success);
+        assert!(SystemRunner::new(false).run(&missing).is_err());
+
+        let echo = Invocation::new("sh").args(["-c", "echo hi; exit 3"]).output(OutputMode::Capture);
+        let output = SystemRunner::new(false).run(&echo).unwrap();
+        assert!(!output.success);
+        assert_eq!(output.code, Some(3));
+        assert_eq!(output.stdout, b"hi\n");
+        assert_eq!(output.to_string(), "exit status: 3");
+
+        let cat = Invocation::new("cat").input(b"paged text").output(OutputMode::Capture);
+        assert_eq!(SystemRunner::new(false).run(&cat).unwrap().stdout, b"paged text");
+    }
+
+    #[test]
+    fn test_pacman_calls_through_fake_runner() {
+        use lilac_aur::alpm::{Pacman, RemoveOptions};
+        use lilac_aur::exec::{ExecOutput, FakeRunner};
+        use std::path::PathBuf;
+
+        let runner = FakeRunner::new();
+        let pacman = Pacman::new(&runner);
+        pacman.install_repo_packages(&["cmake".into(), "ninja".into()]).unwrap();
+        pacman.install_packages(&[PathBuf::from("/cache/foo-1.0-1-x86_64.pkg.tar.zst")]).unwrap();
+        pacman.remove_package(&["foo".into()], RemoveOptions { cascade: true, nosave: false }).unwrap();
+
+        runner.respond(ExecOutput { success: false, code: Some(1), ..Default::default() });
+        assert!(pacman.force_remove_package("bar").is_err());
+
+        assert_eq!(runner.command_lines(), vec![
+            "sudo pacman -S --needed cmake ninja",
+            "sudo pacman -U /cache/foo-1.0-1-x86_64.pkg.tar.zst",
+            "sudo pacman -Rsc foo",
+            "sudo pacman -R bar",
+        ]);
+    }
+
+    #[test]
+    fn test_execute_makepkg_through_fake_runner() {
+        use lilac_aur::build::{MakepkgOptions, PackageBuilder};
+        use lilac_aur::exec::{ExecOutput, FakeRunner, OutputMode};
+        use std::path::Path;
+
+        let runner = FakeRunner::new();
+        PackageBuilder::execute_makepkg("foo", Path::new("/tmp/build/foo"), &MakepkgOptions::default(), &runner).unwrap();
+        runner.respond(ExecOutput { success: false, code: Some(4), ..Default::default() });
+        let err = PackageBuilder::execute_makepkg("foo", Path::new("/tmp/build/foo"), &MakepkgOptions::default(), &runner).unwrap_err();
+        assert!(err.to_string().contains("exit status: 4"));
+
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].to_string(), "makepkg --syncdeps --cleanbuild (in /tmp/build/foo)");
+        assert_eq!(calls[0].output, OutputMode::Null);
+    }
+
+    #[test]
+    fn test_srcinfo_generation_through_runner() {
+        use lilac_aur::exec::{ExecOutput, FakeRunner, SystemRunner};
+        use lilac_aur::srcinfo::SrcInfo;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("PKGBUILD"), "pkgname=foo\n").unwrap();
+
+        // Dry-run prints the makepkg call, writes nothing and can't plan without a .SRCINFO
+        let err = SrcInfo::load_or_generate(dir.path(), &SystemRunner::new(true)).unwrap_err();
+        assert!(err.to_string().contains("No .SRCINFO"));
+        assert!(!dir.path().join(".SRCINFO").exists());
+
+        let runner = FakeRunner::new();
+        runner.respond(ExecOutput {
+            stdout: b"pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\npkgname = foo\n".to_vec(),
+            ..ExecOutput::ok()
+        });
+        let srcinfo = SrcInfo::load_or_generate(dir.path(), &runner).unwrap();
+        assert_eq!(srcinfo.version(), "1.0-1");
+        assert_eq!(runner.command_lines(), vec![format!("makepkg --printsrcinfo (in {})", dir.path().display())]);
+    }
+
+    #[test]
+    fn test_install_command_with_fake_db_and_runner() {
+        use lilac_aur::alpm::{FakeDb, RepoPackage};
+        use lilac_aur::aur::AurPackage;
+        use lilac_aur::commands::install_targets;
+        use lilac_aur::exec::FakeRunner;
+        use lilac_aur::output::OutputFormat;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        let cache = root.path().join("cache");
+        let clone_dir = root.path().join("clones");
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(
+            "[cache]\ndir = \"{}\"\n[build]\nclone_dir = \"{}\"\n",
+            cache.display(),
+            clone_dir.display()
+        )).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        let aur = MockAur::new()
+            .with_package(AurPackage {
+                name: "foo".into(),
+                package_base: Some("foo".into()),
+                version: "1.0-1".into(),
+                description: None,
+                url: None,
+                maintainer: None,
+                num_votes: 0,
+                popularity: 0.0,
+                out_of_date: None,
+                first_submitted: 0,
+                last_modified: 0,
+            })
+            .with_repo_file("foo", "PKGBUILD", "pkgname=foo\npkgver=1.0\npkgrel=1\n")
+            .with_repo_file("foo", ".SRCINFO", "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\n\tdepends = zlib\npkgname = foo\n")
+            .with_repo_file("foo", &format!("foo-0.9-1-{}.pkg.tar.zst", std::env::consts::ARCH), "");
+        let db = FakeDb::new()
+            .with_installed("zlib", "1.3-1", &[], false)
+            .with_repo_package("extra", RepoPackage::new("cmake", "3.31-1"));
+
+        // makepkg drops the package file into PKGDEST
+        let runner = FakeRunner::new();
+        runner.on_run(|invocation| {
+            if let Some((_, pkgdest)) = invocation.env.iter().find(|(key, _)| key == "PKGDEST") {
+                let file = format!("foo-1.0-1-{}.pkg.tar.zst", std::env::consts::ARCH);
+                std::fs::write(std::path::Path::new(pkgdest).join(file), "").unwrap();
+            }
+        });
+        let alpm = AlpmWrapper::with_db(db).with_runner(runner.clone());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(install_targets(&["foo".into(), "cmake".into()], OutputFormat::Plain, &config, &aur, &alpm)).unwrap();
+
+        let cached = cache.join(format!("foo-1.0-1-{}.pkg.tar.zst", std::env::consts::ARCH));
+        assert!(cached.is_file());
+        assert_eq!(std::fs::read_to_string(cache.join("foo.lilac_deps")).unwrap(), "zlib");
+        // A package left in a kept clone by an older build is never picked up
+        assert!(!cache.join(format!("foo-0.9-1-{}.pkg.tar.zst", std::env::consts::ARCH)).exists());
+        assert!(clone_dir.join("foo").join(format!("foo-0.9-1-{}.pkg.tar.zst", std::env::consts::ARCH)).exists());
+        let lines = runner.command_lines();
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        // Nothing is installed before the build succeeded
+        assert!(lines[0].contains("makepkg --syncdeps"), "{}", lines[0]);
+        assert_eq!(lines[1], "sudo pacman -S --needed cmake");
+        assert_eq!(lines[2], format!("sudo pacman -U {}", cached.display()));
+    }
+
+    #[test]
+    fn test_dry_run_install_plans_from_configured_source() {
+        use lilac_aur::alpm::FakeDb;
+        use lilac_aur::commands::install_targets;
+        use lilac_aur::exec::SystemRunner;
+        use lilac_aur::output::OutputFormat;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        let pkgbuilds = root.path().join("pkgbuilds");
+        std::fs::create_dir_all(pkgbuilds.join("foo")).unwrap();
+        std::fs::write(pkgbuilds.join("foo").join("PKGBUILD"), "pkgname=foo\n").unwrap();
+        std::fs::write(pkgbuilds.join("foo").join(".SRCINFO"), "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\npkgname = foo\n").unwrap();
+        let cache = root.path().join("cache");
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(r#"
+[cache]
+dir = "{}"
+
+[[sources]]
+name = "local"
+kind = "dir"
+url = "{}"
+priority = 10
+"#, cache.display(), pkgbuilds.display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        // The AUR doesn't have foo, so the plan has to come from the dir source
+        let alpm = AlpmWrapper::with_db(FakeDb::new()).with_runner(SystemRunner::new(true));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(install_targets(&["foo".into()], OutputFormat::Plain, &config, &MockAur::new(), &alpm)).unwrap();
+        assert!(std::fs::read_dir(&cache).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_local_packages_are_built_in_dependency_order() {
+        use lilac_aur::alpm::FakeDb;
+        use lilac_aur::build::PackageBuilder;
+        use lilac_aur::exec::FakeRunner;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        for (name, depends) in [("app", "\tdepends = libfoo\n"), ("libfoo", "")] {
+            let dir = root.path().join(name);
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("PKGBUILD"), format!("pkgname={}\n", name)).unwrap();
+            std::fs::write(
+                dir.join(".SRCINFO"),
+                format!("pkgbase = {0}\n\tpkgver = 1.0\n\tpkgrel = 1\n{1}pkgname = {0}\n", name, depends)
+            ).unwrap();
+        }
+        let cache = root.path().join("cache");
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!("[cache]\ndir = \"{}\"\n", cache.display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        // makepkg drops <dir name>-1.0-1 into PKGDEST
+        let runner = FakeRunner::new();
+        runner.on_run(|invocation| {
+            if let Some((_, pkgdest)) = invocation.env.iter().find(|(key, _)| key == "PKGDEST") {
+                let pkgbase = invocation.dir.as_ref().unwrap().file_name().unwrap().to_string_lossy();
+                let file = format!("{}-1.0-1-{}.pkg.tar.zst", pkgbase, std::env::consts::ARCH);
+                std::fs::write(std::path::Path::new(pkgdest).join(file), "").unwrap();
+            }
+        });
+        let alpm = AlpmWrapper::with_db(FakeDb::new()).with_runner(runner.clone());
+
+        // libfoo is in neither the repos nor the AUR, only next to app
+        let dirs = [root.path().join("app"), root.path().join("libfoo")];
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let summary = rt.block_on(PackageBuilder::build_local_packages(&dirs, &MockAur::new(), &alpm, &config)).unwrap();
+
+        let names: Vec<&str> = summary.targets.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(names, vec!["libfoo", "app"]);
+        assert!(summary.dependencies.is_empty());
+        let libfoo = cache.join(format!("libfoo-1.0-1-{}.pkg.tar.zst", std::env::consts::ARCH));
+        let lines = runner.command_lines();
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(lines[0].contains("makepkg --syncdeps") && lines[0].contains("libfoo"), "{}", lines[0]);
+        assert_eq!(lines[1], format!("sudo pacman -U {}", libfoo.display()));
+        assert!(lines[2].contains("makepkg --syncdeps") && lines[2].ends_with("/app)"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_dry_run_get_writes_nothing() {
+        use lilac_aur::alpm::FakeDb;
+        use lilac_aur::commands::get_pkgbuilds;
+        use lilac_aur::exec::SystemRunner;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        let pkgbuilds = root.path().join("pkgbuilds");
+        for (name, depends) in [("foo", "\tdepends = bar\n"), ("bar", "")] {
+            std::fs::create_dir_all(pkgbuilds.join(name)).unwrap();
+            std::fs::write(pkgbuilds.join(name).join("PKGBUILD"), format!("pkgname={}\n", name)).unwrap();
+            std::fs::write(
+                pkgbuilds.join(name).join(".SRCINFO"),
+                format!("pkgbase = {0}\n\tpkgver = 1.0\n\tpkgrel = 1\n{1}pkgname = {0}\n", name, depends)
+            ).unwrap();
+        }
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(r#"
+[cache]
+dir = "{}"
+
+[[sources]]
+name = "local"
+kind = "dir"
+url = "{}"
+priority = 10
+"#, root.path().join("cache").display(), pkgbuilds.display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        // Following foo's dependency to bar needs the .SRCINFO a real run would clone
+        let dest = root.path().join("get");
+        let alpm = AlpmWrapper::with_db(FakeDb::new()).with_runner(SystemRunner::new(true));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(get_pkgbuilds(&["foo".into()], &dest, true, &config, &MockAur::new(), &alpm)).unwrap();
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn test_remove_command_with_fake_db_and_runner() {
+        use lilac_aur::alpm::FakeDb;
+        use lilac_aur::commands::{Commands, GlobalOpts, handle_command};
+        use lilac_aur::exec::FakeRunner;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        let cache = root.path().join("cache");
+        std::fs::create_dir(&cache).unwrap();
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!("[cache]\ndir = \"{}\"\n", cache.display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        std::fs::write(cache.join("libfoo-1.0-1-x86_64.pkg.tar.zst"), "").unwrap();
+        std::fs::write(cache.join("libfoo.lilac_deps"), "zlib-ng").unwrap();
+        let db = FakeDb::new()
+            .with_installed("libfoo", "1.0-1", &["zlib-ng"], true)
+            .with_installed("app", "2.0-1", &["libfoo>=1.0"], true)
+            .with_installed("zlib-ng", "2.2-1", &[], false);
+        let runner = FakeRunner::new();
+        let alpm = AlpmWrapper::with_db(db).with_runner(runner.clone());
+
+        let command = Commands::Remove { packages: vec!["libfoo".into()], cascade: true, nosave: false };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(handle_command(command, &GlobalOpts::default(), &config, &MockAur::new(), &alpm)).unwrap();
+
+        assert_eq!(runner.command_lines(), vec!["sudo pacman -Rsc libfoo app zlib-ng"]);
+        assert!(!cache.join("libfoo-1.0-1-x86_64.pkg.tar.zst").exists());
+    }
+
+    #[test]
+    fn test_config_layers_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, r#"
+[build]
+jobs = 2
+makepkg_args = ["--nocheck"]
+
+[behavior]
+ignore = ["foo"]
+
+[package.bar]
+makepkg_args = ["--skippgpcheck"]
+"#).unwrap();
+
+        let config = AppConfig::from_layers(None, Some(&path), &[]).unwrap();
+        assert_eq!(config.build.jobs, Some(2));
+        assert!(config.behavior.confirm);
+        assert!(config.is_ignored("foo"));
+        assert_eq!(config.tools.escalation, "sudo");
+
+        let overrides = vec![
+            ("build.jobs".to_string(), "8".to_string()),
+            ("behavior.ignore".to_string(), "baz, qux".to_string()),
+            ("behavior.confirm".to_string(), "false".to_string()),
+        ];
+        let config = AppConfig::from_layers(None, Some(&path), &overrides).unwrap();
+        assert_eq!(config.build.jobs, Some(8));
+        assert!(!config.behavior.confirm);
+        assert!(!config.is_ignored("foo"));
+        assert!(config.is_ignored("qux"));
+
+        let options = config.makepkg_options("bar");
+        assert_eq!(options.args, vec!["--nocheck", "--skippgpcheck"]);
+        assert!(options.noconfirm);
+        assert_eq!(config.makepkg_options("other").args, vec!["--nocheck"]);
+    }
+
+    #[test]
+    fn test_cli_config_overrides() {
+        use clap::Parser;
+        use lilac_aur::commands::GlobalOpts;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            opts: GlobalOpts,
+        }
+
+        let cli = Cli::try_parse_from([
+            "lilac", "--set", "build.jobs=4", "--clone-dir", "/srv/clones", "--noconfirm",
+        ]).unwrap();
+        assert_eq!(cli.opts.config_overrides(), vec![
+            ("build.jobs".to_string(), "4".to_string()),
+            ("build.clone_dir".to_string(), "/srv/clones".to_string()),
+            ("behavior.confirm".to_string(), "false".to_string()),
+        ]);
+        assert!(Cli::try_parse_from(["lilac", "--set", "build.jobs"]).is_err());
+    }
+
+    #[test]
+    fn test_makepkg_and_pacman_options_invocations() {
+        use lilac_aur::alpm::PacmanOptions;
+        use lilac_aur::build::MakepkgOptions;
+        use std::path::{Path, PathBuf};
+
+        let options = MakepkgOptions {
+            args: vec!["--nocheck".into()],
+            config: Some(PathBuf::from("/x")),
+            jobs: Some(4),
+            noconfirm: true,
+            env: Vec::new(),
+            pkgdest: Some(PathBuf::from("/tmp/pkgdest/foo")),
+        };
+        assert_eq!(
+            options.invocation(Path::new("/tmp/build/foo"), &["--syncdeps"]).to_string(),
+            "MAKEFLAGS=-j4 PKGDEST=/tmp/pkgdest/foo makepkg --syncdeps --config /x --nocheck --noconfirm (in /tmp/build/foo)",
+        );
+
+        let doas = PacmanOptions { escalation: "doas".into(), noconfirm: true };
+        assert_eq!(doas.invocation().arg("-Syu").to_string(), "doas pacman --noconfirm -Syu");
+        let root = PacmanOptions { escalation: String::new(), noconfirm: false };
+        assert_eq!(root.invocation().arg("-Syu").to_string(), "pacman -Syu");
+    }
+
+    #[test]
+    fn test_config_validate_reports_lines() {
+        use lilac_aur::config::ConfigOrigin;
+        use lilac_aur::config_file::validate;
+        let validate = |content| validate(content, ConfigOrigin::UserFile);
+
+        let content = "rpc_timeout = 10\nrpc_retires = 2\n\n[build]\njobs = \"many\"\n\n[package.foo]\nmakepkg_flags = []\n";
+        let issues: Vec<String> = validate(content).iter().map(ToString::to_string).collect();
+        assert_eq!(issues.len(), 3, "{:?}", issues);
+        assert_eq!(issues[0], "line 2: unknown key `rpc_retires`");
+        assert!(issues[1].starts_with("line 5: invalid type"), "{}", issues[1]);
+        assert_eq!(issues[2], "line 8: unknown key `package.foo.makepkg_flags`");
+
+        assert_eq!(validate("[build\n")[0].line, 1);
+        assert!(validate("[behavior]\nconfirm = false\n").is_empty());
+    }
+
+    #[test]
+    fn test_config_set_keeps_comments() {
+        use lilac_aur::config::ConfigOrigin;
+        use lilac_aur::config_file::set_value;
+        let set_value = |content: &str, key, value| set_value(content, key, value, ConfigOrigin::UserFile);
+
+        let content = "# Seconds before a request times out\nrpc_timeout = 10 # seconds\n\n[build]\n# Parallel jobs\njobs = 2\n";
+        let edited = set_value(content, "rpc_timeout", "30").unwrap();
+        assert!(edited.contains("# Seconds before a request times out\nrpc_timeout = 30 # seconds\n"), "{}", edited);
+
+        let edited = set_value(&edited, "build.jobs", "8").unwrap();
+        assert!(edited.contains("# Parallel jobs\njobs = 8\n"), "{}", edited);
+
+        let edited = set_value(&edited, "behavior.ignore", "foo, bar").unwrap();
+        assert!(edited.contains("[behavior]\nignore = [\"foo\", \"bar\"]\n"), "{}", edited);
+        let edited = set_value(&edited, "package.foo.makepkg_args", "--nocheck").unwrap();
+        assert!(edited.contains("[package.foo]\nmakepkg_args = [\"--nocheck\"]\n"), "{}", edited);
+        assert!(!edited.contains("[package]\n"));
+
+        // Strings that look like numbers stay strings
+        let edited = set_value(&edited, "user_agent", "8").unwrap();
+        assert!(edited.contains("user_agent = \"8\""), "{}", edited);
+
+        assert!(set_value(&edited, "build.jbos", "8").unwrap_err().to_string().contains("unknown key"));
+        assert!(set_value(&edited, "rpc_retries", "many").is_err());
+    }
+
+    #[test]
+    fn test_config_origins() {
+        use lilac_aur::config::{ConfigLayers, ConfigOrigin};
+        use lilac_aur::config_file::{effective_settings, effective_value};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "rpc_timeout = 30\n[build]\njobs = 2\n").unwrap();
+
+        let overrides = vec![("build.jobs".to_string(), "6".to_string())];
+        let layers = ConfigLayers::read(None, Some(&path), &overrides).unwrap();
+        let config = layers.merge().unwrap();
+        assert_eq!(layers.origin("rpc_timeout"), ConfigOrigin::UserFile);
+        assert_eq!(layers.origin("build.jobs"), ConfigOrigin::Flag);
+        assert_eq!(layers.origin("rpc_retries"), ConfigOrigin::Default);
+
+        let settings = effective_settings(&config, &layers).unwrap();
+        let jobs = settings.iter().find(|setting| setting.key == "build.jobs").unwrap();
+        assert_eq!(jobs.value, toml::Value::Integer(6));
+        assert_eq!(jobs.origin.to_string(), "flag");
+        assert!(settings.iter().any(|setting| setting.key == "rpc_retries" && setting.origin == ConfigOrigin::Default));
+
+        assert_eq!(effective_value(&config, "rpc_timeout").unwrap(), Some(toml::Value::Integer(30)));
+        assert_eq!(effective_value(&config, "proxy").unwrap(), None);
+    }
+
+    #[test]
+    fn test_config_validate_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[behavior]\nconfrim = false\n").unwrap();
+
+        Command::cargo_bin("lilac").unwrap()
+            .args(["config", "validate"])
+            .arg(&path)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("config.toml:2: unknown key `behavior.confrim`"));
+
+        std::fs::write(&path, "[behavior]\nconfirm = false\n").unwrap();
+        Command::cargo_bin("lilac").unwrap()
+            .args(["config", "validate"])
+            .arg(&path)
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn test_system_config_locks_keys() {
+        use lilac_aur::config::{ConfigLayers, ConfigOrigin};
+        use lilac_aur::config_file::validate;
+
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("system.toml");
+        let user = dir.path().join("user.toml");
+        let system_content = "aur_base_url = \"https://aur.example.com\"\nrpc_retries = 5\nlocked = [\"aur_base_url\", \"behavior\"]\n";
+        std::fs::write(&system, system_content).unwrap();
+        std::fs::write(&user, "aur_base_url = \"https://other.example.com\"\nrpc_retries = 1\n[behavior]\nconfirm = false\n").unwrap();
+
+        let overrides = vec![("behavior.ignore".to_string(), "foo".to_string())];
+        let layers = ConfigLayers::read(Some(&system), Some(&user), &overrides).unwrap();
+        let config = layers.merge().unwrap();
+        assert_eq!(config.aur_base_url, "https://aur.example.com");
+        assert!(config.behavior.confirm);
+        assert!(config.behavior.ignore.is_empty());
+        assert_eq!(config.rpc_retries, 1);
+        assert_eq!(config.locked, vec!["aur_base_url", "behavior"]);
+
+        assert!(layers.is_locked("behavior.confirm"));
+        assert!(!layers.is_locked("behavior_extra"));
+        assert_eq!(layers.origin("aur_base_url"), ConfigOrigin::SystemFile);
+        assert_eq!(layers.origin("behavior.confirm"), ConfigOrigin::Default);
+        assert_eq!(layers.origin("rpc_retries"), ConfigOrigin::UserFile);
+
+        // config/default in the working directory overrides the user config, but
+        // can't set locked keys either
+        let cwd = dir.path().join("project");
+        std::fs::create_dir_all(cwd.join("config")).unwrap();
+        std::fs::write(cwd.join("config").join("default.toml"), "rpc_timeout = 7
+rpc_retries = 3
+[behavior]
+confirm = false
+").unwrap();
+        let layers = ConfigLayers::read_in(&cwd, Some(&system), Some(&user), &[]).unwrap();
+        let config = layers.merge().unwrap();
+        assert!(config.behavior.confirm);
+        assert_eq!(config.rpc_timeout, 7);
+        assert_eq!(config.rpc_retries, 3);
+        assert_eq!(layers.origin("behavior.confirm"), ConfigOrigin::Default);
+        assert_eq!(layers.origin("rpc_timeout"), ConfigOrigin::LocalFile);
+        assert_eq!(layers.origin("rpc_retries"), ConfigOrigin::LocalFile);
+
+        // Only the system config may lock keys
+        assert!(validate(system_content, ConfigOrigin::SystemFile).is_empty());
+        let issues = validate("rpc_retries = 1\nlocked = [\"proxy\"]\n", ConfigOrigin::UserFile);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 2);
+    }
+
+    #[test]
+    fn test_package_override_env_and_args() {
+        use std::path::Path;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, r#"
+[build]
+jobs = 4
+
+[package.foo]
+makepkg_args = ["--nocheck"]
+env = { CFLAGS = "-O2", MAKEFLAGS = "-j1" }
+
+[package.bar]
+env = { RUSTFLAGS = "-Copt-level=2" }
+"#).unwrap();
+        let config = AppConfig::from_layers(None, Some(&path), &[]).unwrap();
+
+        assert_eq!(
+            config.makepkg_options("foo").invocation(Path::new("/b/foo"), &["--syncdeps"]).to_string(),
+            "CFLAGS=-O2 MAKEFLAGS=-j1 makepkg --syncdeps --nocheck (in /b/foo)",
+        );
+        assert_eq!(
+            config.makepkg_options("bar").invocation(Path::new("/b/bar"), &[]).to_string(),
+            "MAKEFLAGS=-j4 RUSTFLAGS=-Copt-level=2 makepkg (in /b/bar)",
+        );
+    }
+
+    #[test]
+    fn test_clone_repo_pins_ref_and_applies_patches() {
+        use git2::{Repository, Signature};
+        use lilac_aur::MockAur;
+        use lilac_aur::build::PackageBuilder;
+
+        fn commit_file(repo: &Repository, content: &str) -> git2::Oid {
+            std::fs::write(repo.workdir().unwrap().join("PKGBUILD"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new("PKGBUILD")).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::now("test", "test@example.com").unwrap();
+            let parents: Vec<git2::Commit> = repo.head().ok()
+                .map(|head| vec![head.peel_to_commit().unwrap()])
+                .unwrap_or_default();
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            repo.commit(Some("HEAD"), &sig, &sig, "update", &tree, &parents).unwrap()
+        }
+
+        let root = tempfile::tempdir().unwrap();
+        let origin = Repository::init(root.path().join("repos").join("foo.git")).unwrap();
+        let pinned = commit_file(&origin, "pkgname=foo\npkgver=1\npkgrel=1\n");
+        commit_file(&origin, "pkgname=foo\npkgver=2\npkgrel=1\n");
+
+        let patches = root.path().join("patches");
+        std::fs::create_dir(&patches).unwrap();
+        std::fs::write(patches.join("01-rel.patch"), "\
+diff --git a/PKGBUILD b/PKGBUILD
+--- a/PKGBUILD
++++ b/PKGBUILD
+@@ -1,3 +1,3 @@
+ pkgname=foo
+ pkgver=1
+-pkgrel=1
++pkgrel=2
+").unwrap();
+        std::fs::write(patches.join("README"), "not a patch").unwrap();
+
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(r#"
+[[sources]]
+name = "local"
+kind = "git"
+url = "file://{}"
+priority = 10
+
+[package.foo]
+ref = "{}"
+patch_dir = "{}"
+"#, root.path().join("repos").display(), pinned, patches.display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        let dest = root.path().join("build").join("foo");
+        let source = PackageBuilder::clone_repo("foo", &dest, &MockAur::new(), &config).unwrap();
+        assert_eq!(source, "local");
+        assert_eq!(std::fs::read_to_string(dest.join("PKGBUILD")).unwrap(), "pkgname=foo\npkgver=1\npkgrel=2\n");
+        assert_eq!(Repository::open(&dest).unwrap().head().unwrap().target(), Some(pinned));
+
+        // A patch that doesn't apply fails the clone
+        std::fs::write(patches.join("02-bad.patch"), "\
+diff --git a/PKGBUILD b/PKGBUILD
+--- a/PKGBUILD
++++ b/PKGBUILD
+@@ -1,1 +1,1 @@
+-pkgname=bar
++pkgname=baz
+").unwrap();
+        let dest = root.path().join("build").join("foo-again");
+        let err = PackageBuilder::clone_repo("foo", &dest, &MockAur::new(), &config).unwrap_err();
+        assert!(err.to_string().contains("Git operation failed"), "{}", err);
+
+        // A branch other than the default one only exists on origin in a fresh clone
+        origin.branch("stable", &origin.find_commit(pinned).unwrap(), false).unwrap();
+        std::fs::write(&config_path, format!(r#"
+[[sources]]
+name = "local"
+kind = "git"
+url = "file://{}"
+priority = 10
+
+[package.foo]
+ref = "stable"
+"#, root.path().join("repos").display())).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+        let dest = root.path().join("build").join("foo-stable");
+        PackageBuilder::clone_repo("foo", &dest, &MockAur::new(), &config).unwrap();
+        assert_eq!(Repository::open(&dest).unwrap().head().unwrap().target(), Some(pinned));
+    }
+
+    #[test]
+    fn test_patched_srcinfo_is_regenerated_and_pinned_packages_are_not_updated() {
+        use lilac_aur::alpm::FakeDb;
+        use lilac_aur::aur::AurPackage;
+        use lilac_aur::commands::{Commands, GlobalOpts, handle_command, install_targets};
+        use lilac_aur::exec::{ExecOutput, FakeRunner};
+        use lilac_aur::output::OutputFormat;
+        use lilac_aur::{AlpmWrapper, MockAur};
+
+        let root = tempfile::tempdir().unwrap();
+        let cache = root.path().join("cache");
+        let patches = root.path().join("patches");
+        std::fs::create_dir(&patches).unwrap();
+        std::fs::write(patches.join("01-rel.patch"), "\
+diff --git a/PKGBUILD b/PKGBUILD
+--- a/PKGBUILD
++++ b/PKGBUILD
+@@ -1,3 +1,3 @@
+ pkgname=foo
+ pkgver=1.0
+-pkgrel=1
++pkgrel=2
+").unwrap();
+        let config_path = root.path().join("config.toml");
+        std::fs::write(&config_path, format!(
+            "[cache]\ndir = \"{}\"\n[package.foo]\npatch_dir = \"{}\"\n",
+            cache.display(),
+            patches.display()
+        )).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+
+        let package = AurPackage {
+            name: "foo".into(),
+            package_base: Some("foo".into()),
+            version: "1.0-1".into(),
+            description: None,
+            url: None,
+            maintainer: None,
+            num_votes: 0,
+            popularity: 0.0,
+            out_of_date: None,
+            first_submitted: 0,
+            last_modified: 0,
+        };
+        let aur = MockAur::new()
+            .with_package(package.clone())
+            .with_repo_file("foo", "PKGBUILD", "pkgname=foo\npkgver=1.0\npkgrel=1\n")
+            .with_repo_file("foo", ".SRCINFO", "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\npkgname = foo\n");
+
+        // The first call is makepkg --printsrcinfo on the patched PKGBUILD
+        let runner = FakeRunner::new();
+        runner.respond(ExecOutput {
+            stdout: b"pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 2\npkgname = foo\n".to_vec(),
+            ..ExecOutput::ok()
+        });
+        let file = format!("foo-1.0-2-{}.pkg.tar.zst", std::env::consts::ARCH);
+        let built = file.clone();
+        runner.on_run(move |invocation| {
+            if let Some((_, pkgdest)) = invocation.env.iter().find(|(key, _)| key == "PKGDEST") {
+                std::fs::write(std::path::Path::new(pkgdest).join(&built), "").unwrap();
+            }
+        });
+        let alpm = AlpmWrapper::with_db(FakeDb::new()).with_runner(runner.clone());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(install_targets(&["foo".into()], OutputFormat::Plain, &config, &aur, &alpm)).unwrap();
+        let lines = runner.command_lines();
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(lines[0].starts_with("makepkg --printsrcinfo"), "{}", lines[0]);
+        assert_eq!(lines[2], format!("sudo pacman -U {}", cache.join(&file).display()));
+
+        // A newer AUR version doesn't update a package pinned to a ref
+        std::fs::write(&config_path, format!(
+            "[cache]\ndir = \"{}\"\n[package.foo]\nref = \"v1\"\n",
+            cache.display()
+        )).unwrap();
+        let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+        let aur = MockAur::new().with_package(AurPackage { version: "2.0-1".into(), ..package });
+        let runner = FakeRunner::new();
+        let db = FakeDb::new().with_installed("foo", "1.0-2", &[], true);
+        let alpm = AlpmWrapper::with_db(db).with_runner(runner.clone());
+        let command = Commands::Update { packages: vec!["foo".into()] };
+        rt.block_on(handle_command(command, &GlobalOpts::default(), &config, &aur, &alpm)).unwrap();
+        assert!(runner.calls().is_empty());
+    }
+}
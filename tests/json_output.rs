@@ -0,0 +1,169 @@
+//! Commands run with `--format json` must leave nothing but the JSON result on
+//! stdout. libtest writes its own report there, so this runs without the harness:
+//! the binary runs each scenario in a child copy of itself and parses its stdout.
+
+use lilac_aur::alpm::FakeDb;
+use lilac_aur::aur::AurPackage;
+use lilac_aur::commands::{Commands, GlobalOpts, handle_command};
+use lilac_aur::config::AppConfig;
+use lilac_aur::exec::{FakeRunner, SystemRunner};
+use lilac_aur::output::{self, OutputFormat};
+use lilac_aur::{AlpmWrapper, MockAur};
+use std::path::Path;
+use std::process::Command;
+
+const SCENARIO_VAR: &str = "JSON_OUTPUT_SCENARIO";
+
+fn aur_package(name: &str) -> AurPackage {
+    AurPackage {
+        name: name.into(),
+        package_base: Some(name.into()),
+        version: "1.0-1".into(),
+        description: None,
+        url: None,
+        maintainer: None,
+        num_votes: 0,
+        popularity: 0.0,
+        out_of_date: None,
+        first_submitted: 0,
+        last_modified: 0,
+    }
+}
+
+fn srcinfo(name: &str, depends: &[&str]) -> String {
+    let depends: String = depends.iter().map(|dep| format!("\tdepends = {}\n", dep)).collect();
+    format!("pkgbase = {0}\n\tpkgver = 1.0\n\tpkgrel = 1\n{1}pkgname = {0}\n", name, depends)
+}
+
+/// A runner whose makepkg drops `<pkgbase>-1.0-1` into PKGDEST
+fn fake_makepkg() -> FakeRunner {
+    let runner = FakeRunner::new();
+    runner.on_run(|invocation| {
+        if let Some((_, pkgdest)) = invocation.env.iter().find(|(key, _)| key == "PKGDEST")
+            && let Some(pkgbase) = invocation.dir.as_deref().and_then(Path::file_name)
+        {
+            let file = format!("{}-1.0-1-{}.pkg.tar.zst", pkgbase.to_string_lossy(), std::env::consts::ARCH);
+            std::fs::write(Path::new(pkgdest).join(file), "").unwrap();
+        }
+    });
+    runner
+}
+
+/// Runs a scenario the way `main` would with `--format json`. foo comes from a
+/// directory source and depends on bar from the AUR.
+fn run_scenario(scenario: &str) {
+    colored::control::set_override(false);
+    output::route_progress(OutputFormat::Json);
+
+    let root = tempfile::tempdir().unwrap();
+    let pkgbuilds = root.path().join("pkgbuilds");
+    std::fs::create_dir_all(pkgbuilds.join("foo")).unwrap();
+    std::fs::write(pkgbuilds.join("foo").join("PKGBUILD"), "pkgname=foo\n").unwrap();
+    std::fs::write(pkgbuilds.join("foo").join(".SRCINFO"), srcinfo("foo", &["bar", "zlib"])).unwrap();
+
+    // A git source ahead of the directory, with foo pinned to its only commit
+    let mut pinned = String::new();
+    if scenario == "install-pinned" {
+        let repos = root.path().join("repos");
+        let repo = git2::Repository::init(repos.join("foo.git")).unwrap();
+        std::fs::copy(pkgbuilds.join("foo").join("PKGBUILD"), repos.join("foo.git").join("PKGBUILD")).unwrap();
+        std::fs::copy(pkgbuilds.join("foo").join(".SRCINFO"), repos.join("foo.git").join(".SRCINFO")).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[]).unwrap();
+        pinned = format!(r#"
+[[sources]]
+name = "git"
+kind = "git"
+url = "file://{}"
+priority = 20
+
+[package.foo]
+ref = "{}"
+"#, repos.display(), commit);
+    }
+
+    let config_path = root.path().join("config.toml");
+    std::fs::write(&config_path, format!(r#"
+[cache]
+dir = "{}"
+
+[[sources]]
+name = "local"
+kind = "dir"
+url = "{}"
+priority = 10
+{}"#, root.path().join("cache").display(), pkgbuilds.display(), pinned)).unwrap();
+    let config = AppConfig::from_layers(None, Some(&config_path), &[]).unwrap();
+    let aur = MockAur::new()
+        .with_package(aur_package("foo"))
+        .with_package(aur_package("bar"))
+        .with_repo_file("bar", "PKGBUILD", "pkgname=bar\n")
+        .with_repo_file("bar", ".SRCINFO", &srcinfo("bar", &[]));
+    let db = FakeDb::new().with_installed("zlib", "1.3-1", &[], false);
+    let mut opts = GlobalOpts { format: OutputFormat::Json, ..Default::default() };
+
+    let command = match scenario {
+        "build" => {
+            let dir = root.path().join("foo");
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("PKGBUILD"), "pkgname=foo\n").unwrap();
+            std::fs::write(dir.join(".SRCINFO"), srcinfo("foo", &["zlib"])).unwrap();
+            Commands::Build { paths: vec![dir] }
+        }
+        "install" => Commands::Install { packages: vec!["foo".into()], local: false },
+        "install-pinned" => Commands::Install { packages: vec!["foo".into()], local: false },
+        "install-dry-run" => {
+            opts.dry_run = true;
+            Commands::Install { packages: vec!["foo".into()], local: false }
+        }
+        "info" => Commands::Info { package: "foo".into(), deps: true },
+        _ => panic!("unknown scenario {}", scenario),
+    };
+    let alpm = if opts.dry_run {
+        AlpmWrapper::with_db(db).with_runner(SystemRunner::new(true))
+    } else {
+        AlpmWrapper::with_db(db).with_runner(fake_makepkg())
+    };
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(handle_command(command, &opts, &config, &aur, &alpm)).unwrap();
+}
+
+fn run_child(scenario: &str) -> serde_json::Value {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .env(SCENARIO_VAR, scenario)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{} failed:\n{}", scenario, String::from_utf8_lossy(&output.stderr));
+    serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("{} printed more than JSON ({}):\n{}", scenario, e, stdout))
+}
+
+fn main() {
+    if let Ok(scenario) = std::env::var(SCENARIO_VAR) {
+        run_scenario(&scenario);
+        return;
+    }
+
+    let summary = run_child("build");
+    assert_eq!(summary["targets"][0]["name"], "foo");
+    assert_eq!(summary["targets"][0]["source"], "local");
+
+    // The source lines of the clones and dry-run plans stay off stdout
+    for scenario in ["install", "install-dry-run"] {
+        let summary = run_child(scenario);
+        assert_eq!(summary["targets"][0]["name"], "foo", "{}", scenario);
+        assert_eq!(summary["targets"][0]["source"], "local", "{}", scenario);
+        assert_eq!(summary["dependencies"][0]["source"], "aur", "{}", scenario);
+    }
+
+    // So do the ref a pinned package is checked out at
+    let summary = run_child("install-pinned");
+    assert_eq!(summary["targets"][0]["source"], "git");
+
+    let info = run_child("info");
+    assert_eq!(info["name"], "foo");
+    assert_eq!(info["dependencies"], serde_json::json!(["bar", "zlib"]));
+}
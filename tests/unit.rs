@@ -1,6 +1,11 @@
 #[cfg(test)]
 mod tests {
-    use lilac_aur::{AurClient, AlpmWrapper};
+    use lilac_aur::{AurBackend, AurClient, AlpmWrapper, FixtureAur, MockAur, SnapshotAur, SrcInfo};
+    use lilac_aur::aur::{AurPackage, ClientOptions, RetryPolicy, SearchField};
+    use lilac_aur::http_cache::ResponseCache;
+    use lilac_aur::network::NetworkSettings;
+    use lilac_aur::search::parse_selection;
+    use std::time::Duration;
     use mockito::Server;
     use tempfile::tempdir;
     use std::fs::create_dir_all;
@@ -126,7 +131,7 @@ mod tests {
         let temp_dir = tempdir().unwrap();
         let root_path = temp_dir.path().join("root");
         
-        create_dir_all(&root_path.join("var/lib/pacman")).unwrap();
+        create_dir_all(root_path.join("var/lib/pacman")).unwrap();
         
         let etc_dir = root_path.join("etc");
         create_dir_all(&etc_dir).unwrap();
@@ -195,4 +200,630 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_aur_client_get_packages_info() {
+        init_logger();
+
+        let mut server = Server::new();
+
+        let mock_response = r#"{
+            "resultcount": 2,
+            "results": [
+                {
+                    "Name": "foo",
+                    "Version": "1.0.0-1",
+                    "Description": null,
+                    "URL": null,
+                    "NumVotes": 1,
+                    "Popularity": 0.1,
+                    "Maintainer": null,
+                    "FirstSubmitted": 1234567890,
+                    "LastModified": 1234567890
+                },
+                {
+                    "Name": "bar",
+                    "Version": "2.0.0-1",
+                    "Description": null,
+                    "URL": null,
+                    "NumVotes": 2,
+                    "Popularity": 0.2,
+                    "Maintainer": null,
+                    "FirstSubmitted": 1234567890,
+                    "LastModified": 1234567890
+                }
+            ],
+            "type": "multiinfo",
+            "version": 5
+        }"#;
+
+        let _m = server
+            .mock("GET", "/rpc/")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("type".into(), "info".into()),
+                mockito::Matcher::Regex(r"arg\[\]=foo&arg\[\]=bar".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_response)
+            .create();
+
+        let client = AurClient::new(server.url());
+        let result = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(client.get_packages_info(&["foo".to_string(), "bar".to_string()]));
+
+        assert!(result.is_ok(), "Expected Ok, got {:?}", result);
+        let packages = result.unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[1].name, "bar");
+    }
+
+    #[test]
+    fn test_aur_client_encodes_query_args() {
+        init_logger();
+
+        let mut server = Server::new();
+        let search = server
+            .mock("GET", "/rpc/")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("by".into(), "keywords".into()),
+                mockito::Matcher::UrlEncoded("arg".into(), "c++ & rust".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"resultcount": 0, "results": [], "type": "search", "version": 5}"#)
+            .create();
+        let info = server
+            .mock("GET", "/rpc/")
+            .match_query(mockito::Matcher::UrlEncoded("arg".into(), "libc++".into()))
+            .with_status(200)
+            .with_body(r#"{"resultcount": 0, "results": [], "type": "multiinfo", "version": 5}"#)
+            .create();
+
+        let client = AurClient::new(server.url());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(client.search_packages_by("c++ & rust", SearchField::Keywords)).unwrap().is_empty());
+        assert!(matches!(
+            runtime.block_on(client.get_package_info("libc++")),
+            Err(lilac_aur::AurError::NotFound(_))
+        ));
+        search.assert();
+        info.assert();
+    }
+
+    #[test]
+    fn test_aur_client_get_repo_file() {
+        init_logger();
+
+        let mut server = Server::new();
+
+        let _m = server
+            .mock("GET", "/cgit/aur.git/plain/PKGBUILD?h=foo")
+            .with_status(200)
+            .with_body("pkgname=foo\n")
+            .create();
+        let _missing = server
+            .mock("GET", "/cgit/aur.git/plain/missing?h=foo")
+            .with_status(404)
+            .create();
+
+        let client = AurClient::new(server.url());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let content = runtime.block_on(client.get_repo_file("foo", "PKGBUILD"));
+        assert_eq!(content.unwrap(), "pkgname=foo\n");
+        let missing = runtime.block_on(client.get_repo_file("foo", "missing"));
+        assert!(matches!(missing, Err(lilac_aur::AurError::NotFound(_))));
+    }
+
+    fn aur_package(name: &str, description: &str, maintainer: &str) -> AurPackage {
+        AurPackage {
+            name: name.to_string(),
+            package_base: Some(name.to_string()),
+            version: "1.0.0-1".to_string(),
+            description: Some(description.to_string()),
+            url: None,
+            maintainer: Some(maintainer.to_string()),
+            num_votes: 0,
+            popularity: 0.0,
+            out_of_date: None,
+            first_submitted: 1234567890,
+            last_modified: 1234567890,
+        }
+    }
+
+    #[test]
+    fn test_mock_aur_backend() {
+        init_logger();
+
+        let aur = MockAur::new()
+            .with_package(aur_package("foo", "Frobnicates things", "alice"))
+            .with_package(aur_package("foo-git", "Development version", "bob"))
+            .with_repo_file("foo", "PKGBUILD", "pkgname=foo\n");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let found = runtime.block_on(aur.search_packages("foo")).unwrap();
+        assert_eq!(found.len(), 2);
+        let found = runtime.block_on(aur.search_packages_by("frobnicates", SearchField::NameDesc)).unwrap();
+        assert_eq!(found[0].name, "foo");
+        let found = runtime.block_on(aur.search_packages_by("bob", SearchField::Maintainer)).unwrap();
+        assert_eq!(found[0].name, "foo-git");
+        assert!(runtime.block_on(aur.search_packages_by("x", SearchField::Depends)).is_err());
+
+        let missing = runtime.block_on(aur.get_package_info("bar"));
+        assert!(matches!(missing, Err(lilac_aur::AurError::NotFound(_))));
+        let infos = runtime.block_on(aur.get_packages_info(&["foo-git".to_string(), "bar".to_string()])).unwrap();
+        assert_eq!(infos.len(), 1);
+
+        let temp_dir = tempdir().unwrap();
+        let dest = temp_dir.path().join("foo");
+        assert!(aur.clone_repo("foo", &dest).unwrap());
+        assert_eq!(std::fs::read_to_string(dest.join("PKGBUILD")).unwrap(), "pkgname=foo\n");
+        assert!(!aur.clone_repo("bar", &temp_dir.path().join("bar")).unwrap());
+    }
+
+    #[test]
+    fn test_fixture_aur_backend() {
+        init_logger();
+
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().join("fixture");
+        create_dir_all(&root).unwrap();
+        std::fs::write(root.join("packages.json"), r#"{
+            "resultcount": 1,
+            "results": [
+                {
+                    "Name": "foo",
+                    "PackageBase": "foo",
+                    "Version": "1.0.0-1",
+                    "Description": "Frobnicates things",
+                    "URL": null,
+                    "NumVotes": 1,
+                    "Popularity": 0.1,
+                    "Maintainer": "alice",
+                    "FirstSubmitted": 1234567890,
+                    "LastModified": 1234567890
+                }
+            ],
+            "type": "search",
+            "version": 5
+        }"#).unwrap();
+
+        // One committed PKGBUILD, plus an uncommitted change that must not be served
+        let repo_dir = root.join("repos").join("foo");
+        let repo = git2::Repository::init(&repo_dir).unwrap();
+        std::fs::write(repo_dir.join("PKGBUILD"), "pkgname=foo\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("PKGBUILD")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[]).unwrap();
+        std::fs::write(repo_dir.join("PKGBUILD"), "pkgname=changed\n").unwrap();
+
+        let aur = FixtureAur::open(&root).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let found = runtime.block_on(aur.search_packages("fo")).unwrap();
+        assert_eq!(found[0].name, "foo");
+        assert_eq!(runtime.block_on(aur.get_package_info("foo")).unwrap().version, "1.0.0-1");
+        assert_eq!(runtime.block_on(aur.get_repo_file("foo", "PKGBUILD")).unwrap(), "pkgname=foo\n");
+        let missing = runtime.block_on(aur.get_repo_file("foo", ".SRCINFO"));
+        assert!(matches!(missing, Err(lilac_aur::AurError::NotFound(_))));
+
+        let dest = temp_dir.path().join("clone");
+        assert!(aur.clone_repo("foo", &dest).unwrap());
+        assert_eq!(std::fs::read_to_string(dest.join("PKGBUILD")).unwrap(), "pkgname=foo\n");
+        assert!(!aur.clone_repo("bar", &temp_dir.path().join("bar")).unwrap());
+    }
+
+    const META_ARCHIVE: &str = r#"[
+        {
+            "Name": "foo",
+            "PackageBase": "foo-base",
+            "Version": "1:2.0-3",
+            "Description": "Frobnicates things",
+            "URL": null,
+            "NumVotes": 5,
+            "Popularity": 0.5,
+            "OutOfDate": null,
+            "Maintainer": "alice",
+            "FirstSubmitted": 1234567890,
+            "LastModified": 1234567890,
+            "Depends": ["bar>=1.0", "glibc"],
+            "MakeDepends": ["cmake"]
+        },
+        {
+            "Name": "foo-docs",
+            "PackageBase": "foo-base",
+            "Version": "1:2.0-3",
+            "Description": null,
+            "URL": null,
+            "NumVotes": 5,
+            "Popularity": 0.5,
+            "OutOfDate": null,
+            "Maintainer": "alice",
+            "FirstSubmitted": 1234567890,
+            "LastModified": 1234567890
+        }
+    ]"#;
+
+    fn gzip(content: &str) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_aur_backend() {
+        init_logger();
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("packages-meta-ext-v1.json.gz");
+        std::fs::write(&path, gzip(META_ARCHIVE)).unwrap();
+
+        let aur = SnapshotAur::open(&path).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(aur.len(), 2);
+
+        let found = runtime.block_on(aur.search_packages_by("frobnicates", SearchField::NameDesc)).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(runtime.block_on(aur.get_package_info("foo")).unwrap().version, "1:2.0-3");
+        let infos = runtime.block_on(aur.get_packages_info(&["foo-docs".to_string(), "baz".to_string()])).unwrap();
+        assert_eq!(infos.len(), 1);
+
+        let srcinfo = SrcInfo::parse(&runtime.block_on(aur.get_repo_file("foo-base", ".SRCINFO")).unwrap());
+        assert_eq!(srcinfo.version(), "1:2.0-3");
+        assert_eq!(srcinfo.makedepends, vec!["cmake"]);
+        assert_eq!(srcinfo.dependencies_for(&["foo".to_string()]), vec!["bar", "glibc", "cmake"]);
+        assert_eq!(srcinfo.constrained("bar"), "bar>=1.0");
+        assert_eq!(srcinfo.dependencies_for(&["foo-docs".to_string()]), vec!["cmake"]);
+
+        assert!(runtime.block_on(aur.get_repo_file("foo-base", "PKGBUILD")).is_err());
+        let missing = runtime.block_on(aur.get_repo_file("baz", ".SRCINFO"));
+        assert!(matches!(missing, Err(lilac_aur::AurError::NotFound(_))));
+        assert!(aur.clone_repo("foo-base", &temp_dir.path().join("clone")).is_err());
+    }
+
+    #[test]
+    fn test_sync_meta() {
+        init_logger();
+
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/packages-meta-ext-v1.json.gz")
+            .with_status(200)
+            .with_body(gzip(META_ARCHIVE))
+            .create();
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("packages-meta-ext-v1.json.gz");
+        let client = AurClient::new(server.url());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let count = runtime.block_on(lilac_aur::commands::sync_meta(&client, &path)).unwrap();
+        assert_eq!(count, 2);
+        assert!(SnapshotAur::open(&path).is_ok());
+
+        // A broken download leaves the previous snapshot in place
+        let _broken = server
+            .mock("GET", "/packages-meta-ext-v1.json.gz")
+            .with_status(200)
+            .with_body("not gzip")
+            .create();
+        assert!(runtime.block_on(lilac_aur::commands::sync_meta(&client, &path)).is_err());
+        assert_eq!(SnapshotAur::open(&path).unwrap().len(), 2);
+        assert!(!path.with_extension("part").exists());
+    }
+
+    const INFO_RESPONSE: &str = r#"{
+        "resultcount": 1,
+        "results": [
+            {
+                "Name": "foo",
+                "Version": "1.0.0-1",
+                "Description": null,
+                "URL": null,
+                "NumVotes": 1,
+                "Popularity": 0.1,
+                "Maintainer": null,
+                "FirstSubmitted": 1234567890,
+                "LastModified": 1234567890
+            }
+        ],
+        "type": "multiinfo",
+        "version": 5
+    }"#;
+
+    #[test]
+    fn test_aur_client_memoizes_responses() {
+        init_logger();
+
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .expect(1)
+            .create();
+
+        let client = AurClient::new(server.url());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..3 {
+            assert_eq!(runtime.block_on(client.get_package_info("foo")).unwrap().name, "foo");
+        }
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_disk_cache_and_refresh() {
+        init_logger();
+
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .expect(2)
+            .create();
+
+        let temp_dir = tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("rpc");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = |ttl: u64, refresh: bool| AurClient::new(server.url())
+            .with_cache(ResponseCache::on_disk(cache_dir.clone(), Duration::from_secs(ttl), refresh));
+
+        // Fetched once, then served from disk by a later run
+        runtime.block_on(client(600, false).get_package_info("foo")).unwrap();
+        runtime.block_on(client(600, false).get_package_info("foo")).unwrap();
+        // Refreshing skips the stored response
+        runtime.block_on(client(600, true).get_package_info("foo")).unwrap();
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_revalidates_stale_responses() {
+        init_logger();
+
+        let mut server = Server::new();
+        let first = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("etag", "\"abc\"")
+            .with_body(INFO_RESPONSE)
+            .expect(1)
+            .create();
+        let not_modified = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .match_header("if-none-match", "\"abc\"")
+            .with_status(304)
+            .expect(1)
+            .create();
+
+        let temp_dir = tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = || AurClient::new(server.url())
+            .with_cache(ResponseCache::on_disk(temp_dir.path().to_path_buf(), Duration::ZERO, false));
+
+        runtime.block_on(client().get_package_info("foo")).unwrap();
+        let revalidated = runtime.block_on(client().get_package_info("foo")).unwrap();
+        assert_eq!(revalidated.version, "1.0.0-1");
+        first.assert();
+        not_modified.assert();
+    }
+
+    fn fast_retry_client(url: String, max_retries: u32) -> AurClient {
+        AurClient::with_options(url, ClientOptions {
+            retry: RetryPolicy {
+                max_retries,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_secs(1),
+            },
+            ..ClientOptions::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(3));
+        assert_eq!(policy.delay(40), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_aur_client_retries_transient_errors() {
+        init_logger();
+
+        let mut server = Server::new();
+        let unavailable = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(503)
+            .expect(2)
+            .create();
+        let ok = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .expect(1)
+            .create();
+
+        let client = fast_retry_client(server.url(), 3);
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(client.get_package_info("foo"));
+
+        assert_eq!(result.unwrap().name, "foo");
+        unavailable.assert();
+        ok.assert();
+    }
+
+    #[test]
+    fn test_aur_client_gives_up_after_retries() {
+        init_logger();
+
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(500)
+            .expect(3)
+            .create();
+
+        let client = fast_retry_client(server.url(), 2);
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(client.get_package_info("foo"));
+
+        assert!(matches!(result, Err(lilac_aur::AurError::ApiError(_))));
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_rate_limit() {
+        init_logger();
+
+        let mut server = Server::new();
+        let limited = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .expect(1)
+            .create();
+        let _ok = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .create();
+        let too_long = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=bar")
+            .with_status(429)
+            .with_header("retry-after", "3600")
+            .expect(1)
+            .create();
+
+        let client = fast_retry_client(server.url(), 3);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // A short Retry-After is waited out
+        assert_eq!(runtime.block_on(client.get_package_info("foo")).unwrap().name, "foo");
+        limited.assert();
+        // One longer than the policy allows fails without retrying
+        let result = runtime.block_on(client.get_package_info("bar"));
+        assert!(matches!(result, Err(lilac_aur::AurError::RateLimited(_))));
+        too_long.assert();
+    }
+
+    #[test]
+    fn test_aur_client_rpc_error() {
+        init_logger();
+
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/rpc/?v=5&type=search&by=name&arg=a")
+            .with_status(200)
+            .with_body(r#"{"version":5,"type":"error","resultcount":0,"results":[],"error":"Too many package results."}"#)
+            .expect(2)
+            .create();
+
+        let client = AurClient::new(server.url());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..2 {
+            match runtime.block_on(client.search_packages("a")) {
+                Err(lilac_aur::AurError::RpcError(e)) => assert_eq!(e, "Too many package results."),
+                other => panic!("Expected an RPC error, got {:?}", other),
+            }
+        }
+        // Errors are not memoized
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_user_agent() {
+        init_logger();
+
+        let mut server = Server::new();
+        let m = server
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .match_header("user-agent", "lilac-test/1.0")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .create();
+
+        let client = AurClient::with_options(server.url(), ClientOptions {
+            network: NetworkSettings { user_agent: "lilac-test/1.0".to_string(), ..NetworkSettings::default() },
+            ..ClientOptions::default()
+        }).unwrap();
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(client.get_package_info("foo"));
+
+        assert!(result.is_ok(), "Expected Ok, got {:?}", result);
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_proxy() {
+        init_logger();
+
+        // The proxy receives requests for a host that doesn't resolve
+        let mut proxy = Server::new();
+        let m = proxy
+            .mock("GET", "/rpc/?v=5&type=info&arg=foo")
+            .match_header("host", "aur.invalid")
+            .with_status(200)
+            .with_body(INFO_RESPONSE)
+            .create();
+
+        let client = AurClient::with_options("http://aur.invalid".to_string(), ClientOptions {
+            network: NetworkSettings { proxy: Some(proxy.url()), ..NetworkSettings::default() },
+            ..ClientOptions::default()
+        }).unwrap();
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(client.get_package_info("foo"));
+
+        assert!(result.is_ok(), "Expected Ok, got {:?}", result);
+        m.assert();
+    }
+
+    #[test]
+    fn test_aur_client_rejects_bad_network_settings() {
+        let with_network = |network: NetworkSettings| AurClient::with_options(
+            "http://aur.invalid".to_string(),
+            ClientOptions { network, ..ClientOptions::default() }
+        );
+
+        let missing_ca = with_network(NetworkSettings {
+            ca_certificates: vec!["/nonexistent/ca.pem".into()],
+            ..NetworkSettings::default()
+        });
+        assert!(missing_ca.err().unwrap().to_string().contains("/nonexistent/ca.pem"));
+
+        let bad_proxy = with_network(NetworkSettings {
+            proxy: Some("not a url".to_string()),
+            ..NetworkSettings::default()
+        });
+        assert!(bad_proxy.is_err());
+    }
+
+    #[test]
+    fn test_parse_selection_numbers_and_ranges() {
+        assert_eq!(parse_selection("1 3 5-7", 10).unwrap(), vec![0, 2, 4, 5, 6]);
+        assert_eq!(parse_selection("3,1, 3", 5).unwrap(), vec![0, 2]);
+        assert_eq!(parse_selection("1-5 ^2 ^4-5", 5).unwrap(), vec![0, 2]);
+        assert_eq!(parse_selection("  \n", 5).unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_parse_selection_exclusions_only_start_from_all() {
+        assert_eq!(parse_selection("^4", 5).unwrap(), vec![0, 1, 2, 4]);
+        assert_eq!(parse_selection("^1-3", 4).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_parse_selection_rejects_invalid_input() {
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("3-1", 3).is_err());
+        assert!(parse_selection("foo", 3).is_err());
+        assert!(parse_selection("1-", 3).is_err());
+    }
 }